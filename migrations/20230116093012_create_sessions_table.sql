@@ -0,0 +1,11 @@
+CREATE TABLE sessions(
+    session_key TEXT NOT NULL,
+    PRIMARY KEY (session_key),
+    user_id uuid NULL
+        REFERENCES editors (user_id) ON DELETE CASCADE,
+    state JSONB NOT NULL,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NOT NULL
+);
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
+CREATE INDEX sessions_expires_at_idx ON sessions (expires_at);
@@ -0,0 +1,9 @@
+CREATE TABLE login_failures(
+    scope TEXT NOT NULL,
+    key TEXT NOT NULL,
+    PRIMARY KEY (scope, key),
+    failed_attempts INTEGER NOT NULL,
+    last_failed_at timestamptz NOT NULL,
+    locked_until timestamptz NULL
+);
+CREATE INDEX login_failures_last_failed_at_idx ON login_failures (last_failed_at);
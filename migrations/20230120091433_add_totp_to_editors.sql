@@ -0,0 +1,3 @@
+ALTER TABLE editors ADD COLUMN totp_secret BYTEA NULL;
+ALTER TABLE editors ADD COLUMN totp_enabled BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE editors ADD COLUMN totp_last_used_step BIGINT NULL;
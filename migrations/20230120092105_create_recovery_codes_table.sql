@@ -0,0 +1,7 @@
+CREATE TABLE recovery_codes(
+    user_id uuid NOT NULL
+        REFERENCES editors (user_id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    PRIMARY KEY (user_id, code_hash),
+    used_at timestamptz NULL
+);
@@ -0,0 +1,8 @@
+-- Existing editors keep the powers they had so far.
+BEGIN;
+    ALTER TABLE editors ADD COLUMN role TEXT NULL;
+    UPDATE editors SET role = 'owner';
+    ALTER TABLE editors ALTER COLUMN role SET NOT NULL;
+    ALTER TABLE editors ADD CONSTRAINT editors_role_check
+        CHECK (role IN ('owner', 'publisher', 'author', 'viewer'));
+COMMIT;
@@ -0,0 +1 @@
+ALTER TABLE editors ADD COLUMN disabled_at timestamptz NULL;
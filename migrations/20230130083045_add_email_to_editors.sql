@@ -0,0 +1,2 @@
+-- Where password reset links are sent. Editors without one can only be reset through `kobo admin`.
+ALTER TABLE editors ADD COLUMN email TEXT NULL;
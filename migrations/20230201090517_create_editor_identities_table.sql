@@ -0,0 +1,10 @@
+-- Identities at an external OpenID Connect provider, linked to the editor they sign in as.
+CREATE TABLE editor_identities(
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    PRIMARY KEY (issuer, subject),
+    user_id uuid NOT NULL
+        REFERENCES editors (user_id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL
+);
+CREATE INDEX editor_identities_user_id_idx ON editor_identities (user_id);
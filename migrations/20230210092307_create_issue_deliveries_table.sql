@@ -0,0 +1,28 @@
+-- One row per recipient of each issue, tracking where their copy is at.
+CREATE TABLE issue_deliveries (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    status TEXT NOT NULL
+        CHECK (status IN ('queued', 'sent', 'failed', 'bounced')),
+    n_attempts SMALLINT NOT NULL DEFAULT 0,
+    error TEXT NULL,
+    queued_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
+CREATE INDEX issue_deliveries_status_idx ON issue_deliveries (newsletter_issue_id, status);
+
+INSERT INTO issue_deliveries (
+    newsletter_issue_id, subscriber_email, status, n_attempts, queued_at, updated_at
+)
+SELECT newsletter_issue_id, subscriber_email, 'queued', n_retries, now(), now()
+FROM issue_delivery_queue;
+
+-- The delivery log supersedes the record of failed deliveries.
+INSERT INTO issue_deliveries (
+    newsletter_issue_id, subscriber_email, status, n_attempts, error, queued_at, updated_at
+)
+SELECT newsletter_issue_id, subscriber_email, 'failed', n_attempts, error, failed_at, failed_at
+FROM issue_delivery_failures;
+DROP TABLE issue_delivery_failures;
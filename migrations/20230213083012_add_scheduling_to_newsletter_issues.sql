@@ -0,0 +1,11 @@
+-- Issues are either published right away or scheduled for later, until the scheduler
+-- dispatches them or an editor cancels them.
+ALTER TABLE newsletter_issues
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'published'
+        CHECK (status IN ('scheduled', 'published', 'cancelled')),
+    ADD COLUMN publish_at timestamptz NULL,
+    ALTER COLUMN published_at DROP NOT NULL;
+ALTER TABLE newsletter_issues ALTER COLUMN status DROP DEFAULT;
+CREATE INDEX newsletter_issues_scheduled_idx
+    ON newsletter_issues (publish_at)
+    WHERE status = 'scheduled';
@@ -0,0 +1,11 @@
+-- Issues start as drafts, which editors can proofread and test before publishing them.
+ALTER TABLE newsletter_issues DROP CONSTRAINT newsletter_issues_status_check;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_status_check
+    CHECK (status IN ('draft', 'scheduled', 'published', 'cancelled'));
+ALTER TABLE newsletter_issues
+    ADD COLUMN created_by uuid NULL
+        REFERENCES editors (user_id) ON DELETE SET NULL,
+    ADD COLUMN updated_at timestamptz NULL;
+UPDATE newsletter_issues
+SET created_by = published_by, updated_at = COALESCE(published_at, now());
+ALTER TABLE newsletter_issues ALTER COLUMN updated_at SET NOT NULL;
@@ -0,0 +1,3 @@
+-- Editors write issues in Markdown, the HTML and plain text bodies are rendered from it.
+-- Issues written before have no Markdown source.
+ALTER TABLE newsletter_issues ADD COLUMN markdown_content TEXT NULL;
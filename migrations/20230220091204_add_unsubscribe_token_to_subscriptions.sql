@@ -0,0 +1,4 @@
+-- Unsubscribe links carry a token of their own: the confirmation token could otherwise turn an
+-- unsubscribe link into one confirming the subscription again.
+-- Subscribers get one along with the first issue delivered to them.
+ALTER TABLE subscriptions ADD COLUMN unsubscribe_token TEXT NULL UNIQUE;
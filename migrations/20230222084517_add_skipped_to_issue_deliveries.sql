@@ -0,0 +1,9 @@
+-- Recipients who unsubscribed before their copy went out are skipped rather than failed, so that
+-- they neither count as failures nor get their delivery resumed.
+ALTER TABLE issue_deliveries DROP CONSTRAINT issue_deliveries_status_check;
+ALTER TABLE issue_deliveries ADD CONSTRAINT issue_deliveries_status_check
+    CHECK (status IN ('queued', 'sent', 'failed', 'bounced', 'skipped'));
+
+UPDATE issue_deliveries
+SET status = 'skipped'
+WHERE status = 'failed' AND error = 'The subscriber unsubscribed or no longer exists.';
@@ -0,0 +1,3 @@
+-- A key reused for a different request is rejected rather than answered with the saved response.
+-- Keys claimed before this migration have no hash, and are replayed as before until they expire.
+ALTER TABLE idempotency ADD COLUMN request_hash TEXT NULL;
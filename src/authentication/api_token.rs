@@ -0,0 +1,184 @@
+use crate::authentication::AuthError;
+use actix_web::http::header::HeaderMap;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use secrecy::{ExposeSecret, Secret};
+use sha3::Digest;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const TOKEN_PREFIX: &str = "kobo_";
+const TOKEN_RANDOM_LENGTH: usize = 40;
+
+/// What an API token allows its bearer to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    NewsletterPublish,
+    SubscribersRead,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 2] = [Scope::NewsletterPublish, Scope::SubscribersRead];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::NewsletterPublish => "newsletter:publish",
+            Scope::SubscribersRead => "subscribers:read",
+        }
+    }
+}
+
+impl TryFrom<&str> for Scope {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Scope::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| format!("{} is not a supported API token scope.", s))
+    }
+}
+
+/// An API token as listed in the admin area, without the token itself.
+pub struct ApiTokenSummary {
+    pub token_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// Extract the token of an HTTP `Authorization: Bearer` header, if that's the scheme in use.
+pub fn bearer_token(headers: &HeaderMap) -> Result<Option<Secret<String>>, anyhow::Error> {
+    let header_value = match headers.get("Authorization") {
+        Some(header_value) => header_value
+            .to_str()
+            .context("The 'Authorization' header was not a valid UTF-8 string.")?,
+        None => return Ok(None),
+    };
+    Ok(header_value
+        .strip_prefix("Bearer ")
+        .map(|token| Secret::new(token.trim().to_string())))
+}
+
+/// Create a new API token for an editor, returning it in clear.
+///
+/// Only its hash is stored, so this is the only time the token can be shown.
+#[tracing::instrument(name = "Create an API token", skip(pool))]
+pub async fn create_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    name: &str,
+    scopes: &[Scope],
+) -> Result<Secret<String>, anyhow::Error> {
+    let random: String = rand::thread_rng()
+        .sample_iter(Alphanumeric)
+        .map(char::from)
+        .take(TOKEN_RANDOM_LENGTH)
+        .collect();
+    let token = format!("{}{}", TOKEN_PREFIX, random);
+    let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_string()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (token_id, user_id, name, token_hash, scopes, created_at)
+        VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        name,
+        hash_token(&token),
+        &scopes
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a new API token")?;
+    Ok(Secret::new(token))
+}
+
+#[tracing::instrument(name = "List API tokens", skip(pool))]
+pub async fn list_api_tokens(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Vec<ApiTokenSummary>, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        ApiTokenSummary,
+        r#"
+        SELECT token_id, name, scopes, created_at, last_used_at FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve API tokens")?;
+    Ok(tokens)
+}
+
+/// Revoke one of an editor's API tokens. Tokens belonging to someone else are left untouched.
+#[tracing::instrument(name = "Revoke an API token", skip(pool))]
+pub async fn revoke_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    token_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM api_tokens WHERE token_id = $1 AND user_id = $2"#,
+        token_id,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke an API token")?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Find the editor an API token belongs to, provided it grants `scope`.
+///
+/// A token lacking the scope is still an [`AuthError::InvalidCredentials`]: it was not issued
+/// for this.
+#[tracing::instrument(
+    name = "Validate an API token",
+    skip(pool, token),
+    fields(token_id=tracing::field::Empty)
+)]
+pub async fn validate_api_token(
+    pool: &PgPool,
+    token: Secret<String>,
+    scope: Scope,
+) -> Result<Uuid, AuthError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT token_id, user_id, scopes
+        FROM api_tokens JOIN editors USING (user_id)
+        WHERE token_hash = $1 AND disabled_at IS NULL
+        "#,
+        hash_token(token.expose_secret())
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve an API token")?
+    .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown API token")))?;
+    tracing::Span::current().record("token_id", tracing::field::display(row.token_id));
+    if !row.scopes.iter().any(|s| s == scope.as_str()) {
+        return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+            "The API token lacks the '{}' scope",
+            scope.as_str()
+        )));
+    }
+    sqlx::query!(
+        r#"UPDATE api_tokens SET last_used_at = now() WHERE token_id = $1"#,
+        row.token_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the use of an API token")?;
+    Ok(row.user_id)
+}
+
+/// API tokens are long random strings, so a fast hash is all we need to protect them at rest.
+fn hash_token(token: &str) -> String {
+    format!("{:x}", sha3::Sha3_256::digest(token.as_bytes()))
+}
@@ -0,0 +1,31 @@
+mod api_token;
+mod basic;
+mod middleware;
+mod oidc;
+mod password;
+mod password_reset;
+mod roles;
+mod throttle;
+mod two_factor;
+
+pub use api_token::{
+    bearer_token, create_api_token, list_api_tokens, revoke_api_token, validate_api_token,
+    ApiTokenSummary, Scope,
+};
+pub use basic::basic_authentication;
+pub use middleware::{reject_anonymous_users, UserId};
+pub use oidc::{OidcError, OidcFlow, OidcProvider};
+pub use password::{
+    change_password, check_password_length, validate_credentials, AuthError, Credentials,
+    PasswordHashingPolicy,
+};
+pub use password_reset::{
+    check_password_reset_token, consume_password_reset_token, issue_password_reset_token,
+    PasswordReset,
+};
+pub use roles::{get_role, require_permission, Permission, PermissionError, Role};
+pub use throttle::LoginThrottle;
+pub use two_factor::{
+    disable_two_factor, enable_two_factor, is_two_factor_enabled, two_factor_enrollment,
+    verify_second_factor, TotpEnrollment, TotpSecretCipher,
+};
@@ -0,0 +1,331 @@
+use crate::authentication::{PasswordHashingPolicy, Role};
+use crate::configuration::OidcSettings;
+use anyhow::Context;
+use openidconnect::core::{CoreClient, CoreProviderMetadata, CoreResponseType};
+use openidconnect::reqwest::async_http_client;
+use openidconnect::url::Url;
+use openidconnect::{
+    AuthenticationFlow, AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce,
+    PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
+};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::time::Instant;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// An external OpenID Connect identity provider editors can sign in with.
+pub struct OidcProvider {
+    settings: OidcSettings,
+    redirect_url: RedirectUrl,
+    jit_role: Role,
+    /// The last discovered metadata, with when it was discovered.
+    metadata: RwLock<Option<(Instant, CoreProviderMetadata)>>,
+}
+
+/// What we must remember between sending an editor to the provider and their coming back.
+#[derive(Serialize, Deserialize)]
+pub struct OidcFlow {
+    state: String,
+    nonce: String,
+    pkce_verifier: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum OidcError {
+    #[error("The sign-in attempt is invalid or has expired, please try again.")]
+    InvalidFlow(#[source] anyhow::Error),
+    #[error("Your account is not allowed to sign in here.")]
+    NotAllowed,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for OidcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        crate::routes::error_chain_fmt(self, f)
+    }
+}
+
+/// The claims of a verified ID token we care about.
+struct Identity {
+    issuer: String,
+    subject: String,
+    preferred_username: Option<String>,
+    verified_email: Option<String>,
+}
+
+impl OidcProvider {
+    pub fn new(settings: OidcSettings, base_url: &str) -> Result<Self, anyhow::Error> {
+        let redirect_url = RedirectUrl::new(format!("{}/login/oidc/callback", base_url))
+            .context("Invalid OIDC redirect URL")?;
+        let jit_role = Role::try_from(settings.jit_role.as_str()).map_err(anyhow::Error::msg)?;
+        Ok(Self {
+            settings,
+            redirect_url,
+            jit_role,
+            metadata: RwLock::new(None),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.settings.provider_name
+    }
+
+    /// Build the URL to send the editor to, along with the flow to keep in their session.
+    #[tracing::instrument(name = "Start an OIDC sign-in", skip(self))]
+    pub async fn authorization_request(&self) -> Result<(Url, OidcFlow), anyhow::Error> {
+        let client = self.client().await?;
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let (url, state, nonce) = client
+            .authorize_url(
+                AuthenticationFlow::<CoreResponseType>::AuthorizationCode,
+                CsrfToken::new_random,
+                Nonce::new_random,
+            )
+            .add_scope(Scope::new("email".into()))
+            .add_scope(Scope::new("profile".into()))
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+        let flow = OidcFlow {
+            state: state.secret().clone(),
+            nonce: nonce.secret().clone(),
+            pkce_verifier: pkce_verifier.secret().clone(),
+        };
+        Ok((url, flow))
+    }
+
+    /// Finish the flow the provider redirected back to us, returning the editor to log in.
+    ///
+    /// Identities are matched to editors by a previous sign-in, then by verified email address
+    /// if email linking is enabled. Failing that, a new editor is created if just-in-time
+    /// provisioning is enabled. Editors who enabled two-factor authentication here must still
+    /// provide their second factor: it's up to the caller to ask for it.
+    #[tracing::instrument(
+        name = "Finish an OIDC sign-in",
+        skip(self, flow, state, code, pool, policy),
+        fields(subject=tracing::field::Empty, user_id=tracing::field::Empty)
+    )]
+    pub async fn sign_in(
+        &self,
+        flow: OidcFlow,
+        state: &str,
+        code: Secret<String>,
+        pool: &PgPool,
+        policy: &PasswordHashingPolicy,
+    ) -> Result<Uuid, OidcError> {
+        if flow.state != state {
+            return Err(OidcError::InvalidFlow(anyhow::anyhow!(
+                "The OIDC state does not match the one in the session"
+            )));
+        }
+        let client = self.client().await?;
+        let token_response = client
+            .exchange_code(AuthorizationCode::new(code.expose_secret().clone()))
+            .set_pkce_verifier(PkceCodeVerifier::new(flow.pkce_verifier))
+            .request_async(async_http_client)
+            .await
+            .context("Failed to exchange the authorization code")
+            .map_err(OidcError::InvalidFlow)?;
+        let id_token = token_response
+            .id_token()
+            .context("The token response has no ID token")
+            .map_err(OidcError::InvalidFlow)?;
+        let claims = id_token
+            .claims(&client.id_token_verifier(), &Nonce::new(flow.nonce))
+            .context("Failed to verify the ID token")
+            .map_err(OidcError::InvalidFlow)?;
+        let identity = Identity {
+            issuer: claims.issuer().as_str().to_string(),
+            subject: claims.subject().as_str().to_string(),
+            preferred_username: claims.preferred_username().map(|u| u.as_str().to_string()),
+            verified_email: claims
+                .email()
+                .filter(|_| claims.email_verified() == Some(true))
+                .map(|e| e.as_str().to_string()),
+        };
+        tracing::Span::current().record("subject", tracing::field::display(&identity.subject));
+
+        let user_id = self.resolve_editor(pool, policy, &identity).await?;
+        tracing::Span::current().record("user_id", tracing::field::display(user_id));
+        Ok(user_id)
+    }
+
+    async fn client(&self) -> Result<CoreClient, anyhow::Error> {
+        Ok(CoreClient::from_provider_metadata(
+            self.provider_metadata().await?,
+            ClientId::new(self.settings.client_id.clone()),
+            Some(ClientSecret::new(
+                self.settings.client_secret.expose_secret().clone(),
+            )),
+        )
+        .set_redirect_uri(self.redirect_url.clone()))
+    }
+
+    /// Providers are discovered again once the discovery TTL has elapsed, so that key
+    /// rotations are picked up without a round trip on every sign-in.
+    async fn provider_metadata(&self) -> Result<CoreProviderMetadata, anyhow::Error> {
+        if let Some((discovered_at, metadata)) = &*self.metadata.read().await {
+            if discovered_at.elapsed() < self.settings.discovery_ttl() {
+                return Ok(metadata.clone());
+            }
+        }
+        let issuer_url =
+            IssuerUrl::new(self.settings.issuer_url.clone()).context("Invalid OIDC issuer URL")?;
+        let metadata = CoreProviderMetadata::discover_async(issuer_url, async_http_client)
+            .await
+            .context("Failed to discover the OIDC provider")?;
+        *self.metadata.write().await = Some((Instant::now(), metadata.clone()));
+        Ok(metadata)
+    }
+
+    async fn resolve_editor(
+        &self,
+        pool: &PgPool,
+        policy: &PasswordHashingPolicy,
+        identity: &Identity,
+    ) -> Result<Uuid, OidcError> {
+        let linked = sqlx::query!(
+            r#"
+            SELECT user_id, disabled_at
+            FROM editor_identities JOIN editors USING (user_id)
+            WHERE issuer = $1 AND subject = $2
+            "#,
+            identity.issuer,
+            identity.subject
+        )
+        .fetch_optional(pool)
+        .await
+        .context("Failed to look up the editor of an OIDC identity")?;
+        if let Some(linked) = linked {
+            return match linked.disabled_at {
+                Some(_) => Err(OidcError::NotAllowed),
+                None => Ok(linked.user_id),
+            };
+        }
+
+        if let Some(email) = identity
+            .verified_email
+            .as_ref()
+            .filter(|_| self.settings.allow_email_linking)
+        {
+            let editors = sqlx::query!(
+                r#"SELECT user_id FROM editors WHERE email = $1 AND disabled_at IS NULL"#,
+                email
+            )
+            .fetch_all(pool)
+            .await
+            .context("Failed to look up an editor by email")?;
+            // Several editors sharing the address is ambiguous: don't pick one.
+            if let [editor] = editors.as_slice() {
+                link_identity(pool, identity, editor.user_id).await?;
+                return Ok(editor.user_id);
+            }
+        }
+
+        if !self.settings.allow_jit_provisioning {
+            tracing::warn!("No editor matches the OIDC identity");
+            return Err(OidcError::NotAllowed);
+        }
+        self.provision_editor(pool, policy, identity).await
+    }
+
+    #[tracing::instrument(name = "Provision an editor for an OIDC identity", skip_all)]
+    async fn provision_editor(
+        &self,
+        pool: &PgPool,
+        policy: &PasswordHashingPolicy,
+        identity: &Identity,
+    ) -> Result<Uuid, OidcError> {
+        let username = identity
+            .preferred_username
+            .clone()
+            .or_else(|| identity.verified_email.clone())
+            .unwrap_or_else(|| identity.subject.clone());
+        // The editor signs in through the provider: nobody gets to know their local password.
+        let password = Secret::new(
+            rand::thread_rng()
+                .sample_iter(Alphanumeric)
+                .map(char::from)
+                .take(32)
+                .collect::<String>(),
+        );
+        let current_span = tracing::Span::current();
+        let policy = policy.clone();
+        let password_hash = tokio::task::spawn_blocking(move || {
+            current_span.in_scope(|| policy.compute_password_hash(password))
+        })
+        .await
+        .context("Failed to spawn blocking task")??;
+
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        let user_id = sqlx::query!(
+            r#"
+            INSERT INTO editors (user_id, username, password_hash, role, email)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (username) DO NOTHING
+            RETURNING user_id
+            "#,
+            Uuid::new_v4(),
+            username,
+            password_hash.expose_secret(),
+            self.jit_role.as_str(),
+            identity.verified_email
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .context("Failed to create an editor for an OIDC identity")?
+        .map(|row| row.user_id);
+        let user_id = match user_id {
+            Some(user_id) => user_id,
+            None => {
+                tracing::warn!(%username, "The username of the OIDC identity is already taken");
+                return Err(OidcError::NotAllowed);
+            }
+        };
+        sqlx::query!(
+            r#"
+            INSERT INTO editor_identities (issuer, subject, user_id, created_at)
+            VALUES ($1, $2, $3, now())
+            "#,
+            identity.issuer,
+            identity.subject,
+            user_id
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to link an OIDC identity")?;
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit the new editor")?;
+        Ok(user_id)
+    }
+}
+
+async fn link_identity(
+    pool: &PgPool,
+    identity: &Identity,
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO editor_identities (issuer, subject, user_id, created_at)
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        identity.issuer,
+        identity.subject,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to link an OIDC identity")?;
+    Ok(())
+}
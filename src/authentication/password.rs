@@ -0,0 +1,233 @@
+use anyhow::Context;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const MIN_PASSWORD_LENGTH: usize = 12;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error("Too many failed login attempts, please try again later.")]
+    LockedOut(std::time::Duration),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// The argon2 algorithm, version and cost parameters we currently recommend.
+///
+/// New hashes are always computed with them, and stored hashes using weaker parameters are
+/// upgraded the next time their owner authenticates successfully.
+#[derive(Clone)]
+pub struct PasswordHashingPolicy {
+    algorithm: Algorithm,
+    version: Version,
+    params: Params,
+    /// Hash checked when the username is unknown, so that a failed lookup costs as much as a
+    /// wrong password and response times don't reveal which usernames exist.
+    dummy_password_hash: Secret<String>,
+}
+
+impl PasswordHashingPolicy {
+    pub fn new(
+        algorithm: Algorithm,
+        version: Version,
+        params: Params,
+    ) -> Result<Self, anyhow::Error> {
+        let mut policy = Self {
+            algorithm,
+            version,
+            params,
+            dummy_password_hash: Secret::new(String::new()),
+        };
+        policy.dummy_password_hash =
+            policy.compute_password_hash(Secret::new(Uuid::new_v4().to_string()))?;
+        Ok(policy)
+    }
+
+    fn hasher(&self) -> Argon2<'static> {
+        Argon2::new(self.algorithm, self.version, self.params.clone())
+    }
+
+    /// Hash a password with the recommended parameters, returning it in PHC string format.
+    pub fn compute_password_hash(
+        &self,
+        password: Secret<String>,
+    ) -> Result<Secret<String>, anyhow::Error> {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let password_hash = self
+            .hasher()
+            .hash_password(password.expose_secret().as_bytes(), &salt)
+            .context("Failed to hash password")?
+            .to_string();
+        Ok(Secret::new(password_hash))
+    }
+
+    /// Whether `password_hash` was computed with weaker settings than the recommended ones.
+    fn needs_rehash(&self, password_hash: &PasswordHash) -> bool {
+        let algorithm = Algorithm::try_from(password_hash.algorithm).ok();
+        let version = password_hash
+            .version
+            .and_then(|v| Version::try_from(v).ok());
+        let params = match Params::try_from(password_hash) {
+            Ok(params) => params,
+            Err(_) => return true,
+        };
+        algorithm != Some(self.algorithm)
+            || version != Some(self.version)
+            || params.m_cost() < self.params.m_cost()
+            || params.t_cost() < self.params.t_cost()
+            || params.p_cost() < self.params.p_cost()
+    }
+}
+
+#[tracing::instrument(name = "Validate credentials", skip(pool, policy, credentials))]
+pub async fn validate_credentials(
+    pool: &PgPool,
+    policy: &PasswordHashingPolicy,
+    credentials: Credentials,
+) -> Result<Uuid, AuthError> {
+    let (user_id, stored_password_hash) = get_stored_credentials(pool, &credentials.username)
+        .await?
+        .unzip();
+
+    let current_span = tracing::Span::current();
+    let policy = policy.clone();
+    let verified_password_hash = stored_password_hash.clone();
+    let upgraded_password_hash = tokio::task::spawn_blocking(move || {
+        current_span.in_scope(|| {
+            let expected_password_hash =
+                verified_password_hash.unwrap_or_else(|| policy.dummy_password_hash.clone());
+            verify_password_hash(&policy, expected_password_hash, credentials.password)
+        })
+    })
+    .await
+    .context("Failed to spawn blocking task")??;
+
+    // Only reachable for an unknown username if someone guessed the dummy password,
+    // but we still must not let them in.
+    let user_id = user_id
+        .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown username")))?;
+
+    if let (Some(old), Some(new)) = (stored_password_hash, upgraded_password_hash) {
+        // A failed upgrade is not a reason to turn the editor away: we'll retry next time.
+        if let Err(e) = upgrade_password_hash(pool, user_id, old, new).await {
+            tracing::warn!(error.cause_chain = ?e, "Failed to upgrade a password hash");
+        }
+    }
+    Ok(user_id)
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(pool, username))]
+async fn get_stored_credentials(
+    pool: &PgPool,
+    username: &str,
+) -> Result<Option<(Uuid, Secret<String>)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash FROM editors
+        WHERE username = $1 AND disabled_at IS NULL
+        "#,
+        username
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve stored credentials.")?
+    .map(|row| (row.user_id, Secret::new(row.password_hash)));
+    Ok(row)
+}
+
+/// Check the password candidate against the expected hash.
+///
+/// On success, returns a fresh hash of the password if the expected one is due for an upgrade.
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(policy, expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    policy: &PasswordHashingPolicy,
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+) -> Result<Option<Secret<String>>, AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format")?;
+    policy
+        .hasher()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .context("Invalid password")
+        .map_err(AuthError::InvalidCredentials)?;
+    if policy.needs_rehash(&expected_password_hash) {
+        Ok(Some(policy.compute_password_hash(password_candidate)?))
+    } else {
+        Ok(None)
+    }
+}
+
+#[tracing::instrument(name = "Upgrade password hash", skip(pool, old, new))]
+async fn upgrade_password_hash(
+    pool: &PgPool,
+    user_id: Uuid,
+    old: Secret<String>,
+    new: Secret<String>,
+) -> Result<(), anyhow::Error> {
+    // Matching on the old hash makes sure we don't clobber a concurrent password change.
+    sqlx::query!(
+        r#"UPDATE editors SET password_hash = $1 WHERE user_id = $2 AND password_hash = $3"#,
+        new.expose_secret(),
+        user_id,
+        old.expose_secret()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the upgraded password hash")?;
+    Ok(())
+}
+
+/// Make sure a new password has a reasonable length, wherever it is set from.
+pub fn check_password_length(password: &Secret<String>) -> Result<(), String> {
+    let length = password.expose_secret().chars().count();
+    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&length) {
+        return Err(format!(
+            "The new password must be between {} and {} characters long.",
+            MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+#[tracing::instrument(name = "Change password", skip(password, policy, pool))]
+pub async fn change_password(
+    user_id: Uuid,
+    password: Secret<String>,
+    policy: &PasswordHashingPolicy,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let current_span = tracing::Span::current();
+    let policy = policy.clone();
+    let password_hash = tokio::task::spawn_blocking(move || {
+        current_span.in_scope(|| policy.compute_password_hash(password))
+    })
+    .await
+    .context("Failed to spawn blocking task")??;
+    sqlx::query!(
+        r#"UPDATE editors SET password_hash = $1 WHERE user_id = $2"#,
+        password_hash.expose_secret(),
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to change editor's password in the database.")?;
+    Ok(())
+}
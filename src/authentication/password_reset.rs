@@ -0,0 +1,130 @@
+use crate::domain::SubscriberEmail;
+use anyhow::Context;
+use chrono::Utc;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use secrecy::{ExposeSecret, Secret};
+use sha3::Digest;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const TOKEN_LENGTH: usize = 40;
+
+/// A freshly issued password reset token, along with where to send it.
+pub struct PasswordReset {
+    pub email: SubscriberEmail,
+    pub token: Secret<String>,
+}
+
+/// Issue a single-use password reset token for `username`, valid for `ttl`.
+///
+/// Returns `None` if there is no active editor with an email address under that name: callers
+/// must not let that difference show. Any token issued earlier for the same editor stops working.
+#[tracing::instrument(name = "Issue a password reset token", skip(pool))]
+pub async fn issue_password_reset_token(
+    pool: &PgPool,
+    username: &str,
+    ttl: std::time::Duration,
+) -> Result<Option<PasswordReset>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, email AS "email!" FROM editors
+        WHERE username = $1 AND email IS NOT NULL AND disabled_at IS NULL
+        "#,
+        username
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the editor asking for a password reset")?;
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    let email = SubscriberEmail::parse(&row.email).map_err(anyhow::Error::msg)?;
+    let token: String = rand::thread_rng()
+        .sample_iter(Alphanumeric)
+        .map(char::from)
+        .take(TOKEN_LENGTH)
+        .collect();
+    let ttl = chrono::Duration::from_std(ttl).context("The password reset TTL is too large")?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"DELETE FROM password_reset_tokens WHERE user_id = $1"#,
+        row.user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to remove older password reset tokens")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at)
+        VALUES ($1, $2, now(), $3)
+        "#,
+        hash_token(&token),
+        row.user_id,
+        Utc::now() + ttl
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store a password reset token")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the password reset token")?;
+    Ok(Some(PasswordReset {
+        email,
+        token: Secret::new(token),
+    }))
+}
+
+/// Find the editor a password reset token was issued to, if it is still usable.
+#[tracing::instrument(name = "Check a password reset token", skip(pool, token))]
+pub async fn check_password_reset_token(
+    pool: &PgPool,
+    token: &Secret<String>,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id
+        FROM password_reset_tokens JOIN editors USING (user_id)
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+            AND disabled_at IS NULL
+        "#,
+        hash_token(token.expose_secret())
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a password reset token")?;
+    Ok(row.map(|r| r.user_id))
+}
+
+/// Mark a password reset token as used, returning the editor it belongs to.
+///
+/// Only one caller can consume a given token, even when racing.
+#[tracing::instrument(name = "Consume a password reset token", skip(pool, token))]
+pub async fn consume_password_reset_token(
+    pool: &PgPool,
+    token: &Secret<String>,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens SET used_at = now()
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+            AND user_id IN (SELECT user_id FROM editors WHERE disabled_at IS NULL)
+        RETURNING user_id
+        "#,
+        hash_token(token.expose_secret())
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to consume a password reset token")?;
+    Ok(row.map(|r| r.user_id))
+}
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", sha3::Sha3_256::digest(token.as_bytes()))
+}
@@ -0,0 +1,154 @@
+use actix_web::http::StatusCode;
+use actix_web::ResponseError;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::{Display, Formatter};
+use uuid::Uuid;
+
+/// What an editor is allowed to do, from most to least powerful.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Everything, including managing other editors.
+    Owner,
+    /// Send issues to every confirmed subscriber and manage subscribers.
+    Publisher,
+    /// Write drafts for a publisher to send.
+    Author,
+    /// Read-only access to the admin area.
+    Viewer,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Owner, Role::Publisher, Role::Author, Role::Viewer];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Publisher => "publisher",
+            Role::Author => "author",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        match permission {
+            Permission::PublishNewsletter | Permission::ManageSubscribers => {
+                matches!(self, Role::Owner | Role::Publisher)
+            }
+            Permission::WriteDrafts => matches!(self, Role::Owner | Role::Publisher | Role::Author),
+            Permission::ManageEditors => matches!(self, Role::Owner),
+        }
+    }
+}
+
+impl TryFrom<&str> for Role {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.as_str() == s)
+            .ok_or_else(|| format!("{} is not a supported role.", s))
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A privileged action, checked against the editor's [`Role`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    PublishNewsletter,
+    ManageSubscribers,
+    WriteDrafts,
+    ManageEditors,
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Permission::PublishNewsletter => "publish newsletter issues",
+            Permission::ManageSubscribers => "manage subscribers",
+            Permission::WriteDrafts => "write drafts",
+            Permission::ManageEditors => "manage editors",
+        })
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum PermissionError {
+    #[error("Your role does not allow you to {0}.")]
+    Denied(Permission),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PermissionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        crate::routes::error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PermissionError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Denied(_) => StatusCode::FORBIDDEN,
+            Self::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[tracing::instrument(name = "Get editor role", skip(pool))]
+pub async fn get_role(pool: &PgPool, user_id: Uuid) -> Result<Role, anyhow::Error> {
+    let row = sqlx::query!(r#"SELECT role FROM editors WHERE user_id = $1"#, user_id)
+        .fetch_one(pool)
+        .await
+        .context("Failed to retrieve the editor's role")?;
+    Role::try_from(row.role.as_str()).map_err(anyhow::Error::msg)
+}
+
+/// Fail with [`PermissionError::Denied`] unless the editor's role grants `permission`.
+#[tracing::instrument(name = "Check editor permission", skip(pool))]
+pub async fn require_permission(
+    pool: &PgPool,
+    user_id: Uuid,
+    permission: Permission,
+) -> Result<(), PermissionError> {
+    let role = get_role(pool, user_id).await?;
+    if role.can(permission) {
+        Ok(())
+    } else {
+        tracing::warn!(%role, %permission, "Permission denied");
+        Err(PermissionError::Denied(permission))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Permission, Role};
+
+    #[test]
+    fn only_owners_can_manage_editors() {
+        for role in Role::ALL {
+            assert_eq!(role.can(Permission::ManageEditors), role == Role::Owner);
+        }
+    }
+
+    #[test]
+    fn only_owners_and_publishers_can_publish() {
+        assert!(Role::Owner.can(Permission::PublishNewsletter));
+        assert!(Role::Publisher.can(Permission::PublishNewsletter));
+        assert!(!Role::Author.can(Permission::PublishNewsletter));
+        assert!(!Role::Viewer.can(Permission::PublishNewsletter));
+    }
+
+    #[test]
+    fn authors_can_only_write_drafts() {
+        assert!(Role::Author.can(Permission::WriteDrafts));
+        assert!(!Role::Author.can(Permission::ManageSubscribers));
+        assert!(!Role::Viewer.can(Permission::WriteDrafts));
+    }
+}
@@ -0,0 +1,358 @@
+use crate::authentication::{validate_credentials, AuthError, Credentials, PasswordHashingPolicy};
+use crate::configuration::{ClientIpSource, LoginThrottlingSettings};
+use actix_web::HttpRequest;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use uuid::Uuid;
+
+const USERNAME_SCOPE: &str = "username";
+const SECOND_FACTOR_SCOPE: &str = "second_factor";
+const CLIENT_IP_SCOPE: &str = "client_ip";
+const PASSWORD_RESET_SCOPE: &str = "password_reset";
+const PASSWORD_RESET_CLIENT_IP_SCOPE: &str = "password_reset_client_ip";
+
+/// An attempt counted against one of the keys it is throttled by.
+struct Reservation<'a> {
+    scope: &'static str,
+    key: &'a str,
+    previous_lock: Option<DateTime<Utc>>,
+    lock: Option<DateTime<Utc>>,
+}
+
+/// Brute-force protection for everything that checks an editor's password.
+///
+/// Failed attempts are counted per username and per client IP in the `login_failures` table.
+/// Once either count reaches its threshold, further attempts are refused without running
+/// argon2 for a lockout period that doubles with every additional failure, up to a maximum.
+/// Counts are forgotten once the maximum lockout has elapsed since the last failure.
+///
+/// Each attempt is counted before the password is verified and taken back if it succeeds, so
+/// that concurrent guesses can't all get past the check before any of them is recorded.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    pool: PgPool,
+    max_failed_attempts_per_username: i32,
+    max_failed_attempts_per_client_ip: i32,
+    max_password_resets_per_username: i32,
+    max_password_resets_per_client_ip: i32,
+    base_lockout: Duration,
+    max_lockout: Duration,
+    client_ip_source: ClientIpSource,
+}
+
+impl LoginThrottle {
+    pub fn new(pool: PgPool, settings: &LoginThrottlingSettings) -> Self {
+        Self {
+            pool,
+            max_failed_attempts_per_username: settings.max_failed_attempts_per_username,
+            max_failed_attempts_per_client_ip: settings.max_failed_attempts_per_client_ip,
+            max_password_resets_per_username: settings.max_password_resets_per_username,
+            max_password_resets_per_client_ip: settings.max_password_resets_per_client_ip,
+            base_lockout: settings.base_lockout(),
+            max_lockout: settings.max_lockout(),
+            client_ip_source: settings.client_ip_source,
+        }
+    }
+
+    /// The IP failed attempts from `request` are counted against, as configured.
+    pub fn client_ip(&self, request: &HttpRequest) -> Option<IpAddr> {
+        match self.client_ip_source {
+            ClientIpSource::PeerAddress => request.peer_addr().map(|addr| addr.ip()),
+            ClientIpSource::ForwardedHeader => {
+                let connection_info = request.connection_info();
+                let addr = connection_info.realip_remote_addr()?;
+                addr.parse::<IpAddr>()
+                    .ok()
+                    .or_else(|| addr.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+            }
+            ClientIpSource::Disabled => None,
+        }
+    }
+
+    /// [`validate_credentials`], refusing early with [`AuthError::LockedOut`] while the
+    /// username or the client IP is locked out.
+    #[tracing::instrument(
+        name = "Validate credentials with brute-force protection",
+        skip(self, policy, credentials)
+    )]
+    pub async fn authenticate(
+        &self,
+        policy: &PasswordHashingPolicy,
+        credentials: Credentials,
+        client_ip: Option<IpAddr>,
+    ) -> Result<Uuid, AuthError> {
+        let username = credentials.username.clone();
+        self.guard(
+            USERNAME_SCOPE,
+            &username,
+            client_ip,
+            validate_credentials(&self.pool, policy, credentials),
+        )
+        .await
+    }
+
+    /// Brute-force protection for the second login step, given the editor's `username`.
+    ///
+    /// Its failures are counted separately, so that knowing the password (and thus resetting
+    /// the username's count) doesn't buy extra guesses at the second factor.
+    pub async fn authenticate_second_factor<T>(
+        &self,
+        username: &str,
+        client_ip: Option<IpAddr>,
+        attempt: impl Future<Output = Result<T, AuthError>>,
+    ) -> Result<T, AuthError> {
+        self.guard(SECOND_FACTOR_SCOPE, username, client_ip, attempt)
+            .await
+    }
+
+    /// Count a password reset request for `username`, refusing it with [`AuthError::LockedOut`]
+    /// once the username or the client IP has asked for too many, so that nobody can flood an
+    /// editor's inbox or burn through our email quota.
+    ///
+    /// Requests are counted separately from login failures, and never taken back: each one
+    /// sends an email, whether or not the editor asked for it.
+    #[tracing::instrument(name = "Throttle password reset requests", skip(self))]
+    pub async fn throttle_password_reset(
+        &self,
+        username: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(), AuthError> {
+        let client_ip = client_ip.map(|ip| ip.to_string());
+        let keys = keys(
+            (PASSWORD_RESET_SCOPE, username),
+            PASSWORD_RESET_CLIENT_IP_SCOPE,
+            client_ip.as_deref(),
+        );
+        match self.reserve_attempt(&keys).await? {
+            Ok(_) => Ok(()),
+            Err(retry_after) => {
+                tracing::warn!(
+                    retry_after_secs = retry_after.as_secs(),
+                    "Refused a password reset request during a lockout"
+                );
+                Err(AuthError::LockedOut(retry_after))
+            }
+        }
+    }
+
+    /// Run `attempt` unless `key` or `client_ip` is locked out, counting it as a failure
+    /// if it is rejected with [`AuthError::InvalidCredentials`].
+    ///
+    /// `attempt` is not polled at all during a lockout, so it costs us nothing.
+    async fn guard<T>(
+        &self,
+        scope: &'static str,
+        key: &str,
+        client_ip: Option<IpAddr>,
+        attempt: impl Future<Output = Result<T, AuthError>>,
+    ) -> Result<T, AuthError> {
+        let client_ip = client_ip.map(|ip| ip.to_string());
+        let keys = keys((scope, key), CLIENT_IP_SCOPE, client_ip.as_deref());
+
+        let reservations = match self.reserve_attempt(&keys).await? {
+            Ok(reservations) => reservations,
+            Err(retry_after) => {
+                tracing::warn!(
+                    retry_after_secs = retry_after.as_secs(),
+                    "Refused a login attempt during a lockout"
+                );
+                return Err(AuthError::LockedOut(retry_after));
+            }
+        };
+
+        match attempt.await {
+            Ok(outcome) => {
+                self.clear_failures(scope, key).await?;
+                // The client IP keeps its other failures.
+                self.release(&reservations[1..]).await?;
+                Ok(outcome)
+            }
+            Err(AuthError::InvalidCredentials(e)) => Err(AuthError::InvalidCredentials(e)),
+            Err(e) => {
+                self.release(&reservations).await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Count the attempt as a failure before it is made, unless a lockout is in effect: the
+    /// outcome decides whether it is taken back.
+    ///
+    /// The counters are locked while they are checked and bumped, so that concurrent attempts
+    /// can't all slip in under the threshold. The lockout they earn is in place before the
+    /// password is even verified.
+    async fn reserve_attempt<'a>(
+        &self,
+        keys: &[(&'static str, &'a str)],
+    ) -> Result<Result<Vec<Reservation<'a>>, Duration>, anyhow::Error> {
+        sqlx::query!(
+            r#"DELETE FROM login_failures WHERE last_failed_at < $1"#,
+            Utc::now() - chrono::Duration::from_std(self.max_lockout)?
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete stale login failures")?;
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        let mut counters = Vec::with_capacity(keys.len());
+        for (scope, key) in keys {
+            sqlx::query!(
+                r#"
+                INSERT INTO login_failures (scope, key, failed_attempts, last_failed_at)
+                VALUES ($1, $2, 0, now())
+                ON CONFLICT DO NOTHING
+                "#,
+                scope,
+                key
+            )
+            .execute(&mut transaction)
+            .await
+            .context("Failed to create a login failure counter")?;
+            let counter = sqlx::query!(
+                r#"
+                SELECT failed_attempts, locked_until FROM login_failures
+                WHERE scope = $1 AND key = $2
+                FOR UPDATE
+                "#,
+                scope,
+                key
+            )
+            .fetch_one(&mut transaction)
+            .await
+            .context("Failed to lock a login failure counter")?;
+            counters.push((counter.failed_attempts, counter.locked_until));
+        }
+
+        let now = Utc::now();
+        let locked_until = counters
+            .iter()
+            .filter_map(|(_, locked_until)| *locked_until)
+            .filter(|locked_until| *locked_until > now)
+            .max();
+        if let Some(locked_until) = locked_until {
+            // Dropping the transaction leaves the counters as they were.
+            return Ok(Err((locked_until - now).to_std().unwrap_or_default()));
+        }
+
+        let mut reservations = Vec::with_capacity(keys.len());
+        for ((scope, key), (failed_attempts, previous_lock)) in keys.iter().zip(counters) {
+            let failed_attempts = failed_attempts + 1;
+            let lock = match self.lockout_after(scope, failed_attempts) {
+                Some(lockout) => {
+                    tracing::warn!(
+                        scope,
+                        failed_attempts,
+                        lockout_secs = lockout.as_secs(),
+                        "Too many failed login attempts, locking out"
+                    );
+                    Some(now + chrono::Duration::from_std(lockout)?)
+                }
+                None => previous_lock,
+            };
+            sqlx::query!(
+                r#"
+                UPDATE login_failures
+                SET failed_attempts = $3, last_failed_at = now(), locked_until = $4
+                WHERE scope = $1 AND key = $2
+                "#,
+                scope,
+                key,
+                failed_attempts,
+                lock
+            )
+            .execute(&mut transaction)
+            .await
+            .context("Failed to record a login attempt")?;
+            reservations.push(Reservation {
+                scope,
+                key,
+                previous_lock,
+                lock,
+            });
+        }
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit a login attempt")?;
+        Ok(Ok(reservations))
+    }
+
+    /// Take back reserved attempts that turned out not to be failures, along with the lockout
+    /// they earned, unless a concurrent attempt has moved it since.
+    async fn release(&self, reservations: &[Reservation<'_>]) -> Result<(), anyhow::Error> {
+        for reservation in reservations {
+            sqlx::query!(
+                r#"
+                UPDATE login_failures
+                SET failed_attempts = failed_attempts - 1,
+                    locked_until = CASE
+                        WHEN locked_until IS NOT DISTINCT FROM $3 THEN $4
+                        ELSE locked_until
+                    END
+                WHERE scope = $1 AND key = $2
+                "#,
+                reservation.scope,
+                reservation.key,
+                reservation.lock,
+                reservation.previous_lock
+            )
+            .execute(&self.pool)
+            .await
+            .context("Failed to take back a login attempt")?;
+        }
+        Ok(())
+    }
+
+    /// The lockout earned by `failed_attempts` consecutive failures: none below the threshold,
+    /// then doubling from the base lockout with each further failure.
+    fn lockout_after(&self, scope: &str, failed_attempts: i32) -> Option<Duration> {
+        let max_failed_attempts = match scope {
+            CLIENT_IP_SCOPE => self.max_failed_attempts_per_client_ip,
+            PASSWORD_RESET_SCOPE => self.max_password_resets_per_username,
+            PASSWORD_RESET_CLIENT_IP_SCOPE => self.max_password_resets_per_client_ip,
+            _ => self.max_failed_attempts_per_username,
+        };
+        let excess = failed_attempts.checked_sub(max_failed_attempts)?;
+        let excess = u32::try_from(excess).ok()?;
+        let factor = 2u32.checked_pow(excess).unwrap_or(u32::MAX);
+        Some(
+            self.base_lockout
+                .checked_mul(factor)
+                .map_or(self.max_lockout, |lockout| lockout.min(self.max_lockout)),
+        )
+    }
+
+    /// Only `key` is cleared: succeeding for one account says nothing about the other attempts
+    /// coming from the same IP, which is why IPs get a higher threshold.
+    async fn clear_failures(&self, scope: &str, key: &str) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"DELETE FROM login_failures WHERE scope = $1 AND key = $2"#,
+            scope,
+            key
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to clear login failures")?;
+        Ok(())
+    }
+}
+
+/// The keys an attempt is counted against: `primary`, then the client IP if we know it.
+fn keys<'a>(
+    primary: (&'static str, &'a str),
+    client_ip_scope: &'static str,
+    client_ip: Option<&'a str>,
+) -> Vec<(&'static str, &'a str)> {
+    let mut keys = vec![primary];
+    if let Some(client_ip) = client_ip {
+        keys.push((client_ip_scope, client_ip));
+    }
+    keys
+}
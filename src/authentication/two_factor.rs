@@ -0,0 +1,386 @@
+use crate::authentication::AuthError;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, Nonce, OsRng, Payload};
+use aes_gcm::Aes256Gcm;
+use anyhow::Context;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use secrecy::{ExposeSecret, Secret};
+use sha3::Digest;
+use sqlx::PgPool;
+use totp_rs::{Algorithm, TOTP};
+use uuid::Uuid;
+
+const TOTP_ISSUER: &str = "kobo";
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP_SECONDS: u64 = 30;
+/// How many steps a code may be off by, to account for clock drift and slow typists.
+const TOTP_ALLOWED_DRIFT: u64 = 1;
+const TOTP_SECRET_BYTES: usize = 20;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// The key used to encrypt TOTP secrets at rest.
+///
+/// Secrets are sealed with AES-256-GCM, using the editor's id as associated data so that an
+/// encrypted secret can't be moved over to another editor's row.
+#[derive(Clone)]
+pub struct TotpSecretCipher(Aes256Gcm);
+
+impl TotpSecretCipher {
+    /// Build the cipher from a base64-encoded 256-bit key.
+    pub fn parse(encoded_key: &Secret<String>) -> Result<Self, anyhow::Error> {
+        let key = base64::decode(encoded_key.expose_secret())
+            .context("The TOTP encryption key is not valid base64")?;
+        let cipher = Aes256Gcm::new_from_slice(&key)
+            .map_err(|_| anyhow::anyhow!("The TOTP encryption key must be 32 bytes long"))?;
+        Ok(Self(cipher))
+    }
+
+    fn encrypt(&self, user_id: Uuid, secret: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: secret,
+            aad: user_id.as_bytes(),
+        };
+        let ciphertext = self
+            .0
+            .encrypt(&nonce, payload)
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt a TOTP secret"))?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    fn decrypt(&self, user_id: Uuid, sealed: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let nonce_length = Nonce::<Aes256Gcm>::default().len();
+        anyhow::ensure!(sealed.len() > nonce_length, "The TOTP secret is truncated");
+        let (nonce, ciphertext) = sealed.split_at(nonce_length);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: user_id.as_bytes(),
+        };
+        self.0
+            .decrypt(Nonce::<Aes256Gcm>::from_slice(nonce), payload)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt a TOTP secret"))
+    }
+}
+
+/// What an editor needs to add kobo to their authenticator app.
+pub struct TotpEnrollment {
+    /// The `otpauth://` URI, also encoded in the QR code.
+    pub provisioning_uri: String,
+    /// The QR code as an SVG image, ready to be embedded in a page.
+    pub qr_code_svg: String,
+    /// The base32-encoded secret, for manual entry.
+    pub secret: String,
+}
+
+fn totp(secret: Vec<u8>, username: &str) -> TOTP {
+    // Colons separate the issuer from the account name in the provisioning URI label.
+    TOTP::new_unchecked(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        0,
+        TOTP_STEP_SECONDS,
+        secret,
+        Some(TOTP_ISSUER.to_string()),
+        username.replace(':', ""),
+    )
+}
+
+#[tracing::instrument(
+    name = "Check whether two-factor authentication is enabled",
+    skip(pool)
+)]
+pub async fn is_two_factor_enabled(pool: &PgPool, user_id: Uuid) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT totp_enabled FROM editors WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to check whether two-factor authentication is enabled")?;
+    Ok(row.totp_enabled)
+}
+
+/// The TOTP secret an editor who hasn't enabled two-factor authentication yet should add to
+/// their authenticator app.
+///
+/// A secret is generated and stored on the first call, and reused afterwards, but only takes
+/// effect once the editor proves they set it up correctly via [`enable_two_factor`].
+#[tracing::instrument(name = "Get two-factor enrollment", skip(pool, cipher))]
+pub async fn two_factor_enrollment(
+    pool: &PgPool,
+    cipher: &TotpSecretCipher,
+    user_id: Uuid,
+    username: &str,
+) -> Result<TotpEnrollment, anyhow::Error> {
+    let secret = match pending_totp_secret(pool, cipher, user_id).await? {
+        Some(secret) => secret,
+        None => {
+            let secret: [u8; TOTP_SECRET_BYTES] = rand::thread_rng().gen();
+            let sealed_secret = cipher.encrypt(user_id, &secret)?;
+            let result = sqlx::query!(
+                r#"
+                UPDATE editors SET totp_secret = $2
+                WHERE user_id = $1 AND totp_enabled = false AND totp_secret IS NULL
+                "#,
+                user_id,
+                sealed_secret
+            )
+            .execute(pool)
+            .await
+            .context("Failed to store a new TOTP secret")?;
+            if result.rows_affected() == 1 {
+                secret.to_vec()
+            } else {
+                // Someone beat us to it from another tab.
+                pending_totp_secret(pool, cipher, user_id)
+                    .await?
+                    .context("Two-factor enrollment was interrupted")?
+            }
+        }
+    };
+
+    let totp = totp(secret, username);
+    let provisioning_uri = totp.get_url();
+    let qr_code_svg = qrcode::QrCode::new(provisioning_uri.as_bytes())
+        .context("Failed to encode the provisioning URI as a QR code")?
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+    Ok(TotpEnrollment {
+        provisioning_uri,
+        qr_code_svg,
+        secret: totp.get_secret_base32(),
+    })
+}
+
+/// The secret of an enrollment in progress, failing if two-factor authentication is enabled.
+async fn pending_totp_secret(
+    pool: &PgPool,
+    cipher: &TotpSecretCipher,
+    user_id: Uuid,
+) -> Result<Option<Vec<u8>>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT totp_secret, totp_enabled FROM editors WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the TOTP secret")?;
+    anyhow::ensure!(
+        !row.totp_enabled,
+        "Two-factor authentication is already enabled"
+    );
+    row.totp_secret
+        .map(|sealed_secret| cipher.decrypt(user_id, &sealed_secret))
+        .transpose()
+}
+
+/// Turn two-factor authentication on once the editor has entered a valid code for the secret
+/// from [`two_factor_enrollment`].
+///
+/// Returns a fresh set of recovery codes, which won't be retrievable afterwards.
+#[tracing::instrument(name = "Enable two-factor authentication", skip(pool, cipher, code))]
+pub async fn enable_two_factor(
+    pool: &PgPool,
+    cipher: &TotpSecretCipher,
+    user_id: Uuid,
+    code: Secret<String>,
+) -> Result<Vec<Secret<String>>, AuthError> {
+    let secret = pending_totp_secret(pool, cipher, user_id)
+        .await?
+        .ok_or_else(|| {
+            AuthError::InvalidCredentials(anyhow::anyhow!("No two-factor enrollment in progress"))
+        })?;
+    let step = matching_step(&totp(secret, ""), code.expose_secret())
+        .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Invalid TOTP code")))?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let result = sqlx::query!(
+        r#"
+        UPDATE editors SET totp_enabled = true, totp_last_used_step = $2
+        WHERE user_id = $1 AND totp_enabled = false
+        "#,
+        user_id,
+        step
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to enable two-factor authentication")?;
+    if result.rows_affected() != 1 {
+        return Err(anyhow::anyhow!("Two-factor authentication is already enabled").into());
+    }
+    let recovery_codes = replace_recovery_codes(&mut transaction, user_id).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the two-factor enrollment")?;
+    Ok(recovery_codes)
+}
+
+#[tracing::instrument(name = "Disable two-factor authentication", skip(pool))]
+pub async fn disable_two_factor(pool: &PgPool, user_id: Uuid) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"
+        UPDATE editors
+        SET totp_secret = NULL, totp_enabled = false, totp_last_used_step = NULL
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to disable two-factor authentication")?;
+    sqlx::query!(r#"DELETE FROM recovery_codes WHERE user_id = $1"#, user_id)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete recovery codes")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit disabling two-factor authentication")?;
+    Ok(())
+}
+
+/// Check the second factor of an editor with two-factor authentication enabled: either a TOTP
+/// code or one of their recovery codes.
+///
+/// Each TOTP code and each recovery code is only accepted once.
+#[tracing::instrument(name = "Verify second factor", skip(pool, cipher, code))]
+pub async fn verify_second_factor(
+    pool: &PgPool,
+    cipher: &TotpSecretCipher,
+    user_id: Uuid,
+    code: Secret<String>,
+) -> Result<(), AuthError> {
+    let code = code.expose_secret().trim();
+    let is_totp_code = code.len() == TOTP_DIGITS && code.bytes().all(|b| b.is_ascii_digit());
+    let accepted = if is_totp_code {
+        use_totp_code(pool, cipher, user_id, code).await?
+    } else {
+        use_recovery_code(pool, user_id, code).await?
+    };
+    if accepted {
+        Ok(())
+    } else {
+        Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+            "Invalid second factor"
+        )))
+    }
+}
+
+async fn use_totp_code(
+    pool: &PgPool,
+    cipher: &TotpSecretCipher,
+    user_id: Uuid,
+    code: &str,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT totp_secret FROM editors WHERE user_id = $1 AND totp_enabled = true"#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the TOTP secret")?;
+    let sealed_secret = match row.and_then(|r| r.totp_secret) {
+        Some(sealed_secret) => sealed_secret,
+        None => return Ok(false),
+    };
+    let secret = cipher.decrypt(user_id, &sealed_secret)?;
+    let step = match matching_step(&totp(secret, ""), code) {
+        Some(step) => step,
+        None => return Ok(false),
+    };
+    // Codes from a step at or before the last one used are replays.
+    let result = sqlx::query!(
+        r#"
+        UPDATE editors SET totp_last_used_step = $2
+        WHERE user_id = $1 AND (totp_last_used_step IS NULL OR totp_last_used_step < $2)
+        "#,
+        user_id,
+        step
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the TOTP code as used")?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// The time step `code` was generated for, if it is valid right now.
+fn matching_step(totp: &TOTP, code: &str) -> Option<i64> {
+    let now = chrono::Utc::now().timestamp() as u64;
+    let current_step = now / TOTP_STEP_SECONDS;
+    (current_step.saturating_sub(TOTP_ALLOWED_DRIFT)..=current_step + TOTP_ALLOWED_DRIFT)
+        .find(|step| totp.check(code, step * TOTP_STEP_SECONDS))
+        .map(|step| step as i64)
+}
+
+async fn use_recovery_code(
+    pool: &PgPool,
+    user_id: Uuid,
+    code: &str,
+) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE recovery_codes SET used_at = now()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        hash_recovery_code(code)
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the recovery code as used")?;
+    Ok(result.rows_affected() == 1)
+}
+
+async fn replace_recovery_codes(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: Uuid,
+) -> Result<Vec<Secret<String>>, anyhow::Error> {
+    sqlx::query!(r#"DELETE FROM recovery_codes WHERE user_id = $1"#, user_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete old recovery codes")?;
+    let mut recovery_codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let code = generate_recovery_code();
+        sqlx::query!(
+            r#"INSERT INTO recovery_codes (user_id, code_hash) VALUES ($1, $2)"#,
+            user_id,
+            hash_recovery_code(&code)
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to store a recovery code")?;
+        recovery_codes.push(Secret::new(code));
+    }
+    Ok(recovery_codes)
+}
+
+/// A random code such as `x7k2m-q9p4z`, easy enough to type from a printout.
+fn generate_recovery_code() -> String {
+    let mut rng = rand::thread_rng();
+    let mut half = || -> String {
+        (&mut rng)
+            .sample_iter(Alphanumeric)
+            .map(|c| char::from(c).to_ascii_lowercase())
+            .take(5)
+            .collect()
+    };
+    format!("{}-{}", half(), half())
+}
+
+/// Recovery codes are random enough that a fast hash is all we need to protect them at rest.
+fn hash_recovery_code(code: &str) -> String {
+    format!(
+        "{:x}",
+        sha3::Sha3_256::digest(code.trim().to_ascii_lowercase().as_bytes())
+    )
+}
@@ -0,0 +1,259 @@
+//! src/cli.rs
+
+use crate::authentication::{check_password_length, PasswordHashingPolicy, Role};
+use crate::domain::SubscriberEmail;
+use crate::session_store::PgSessionStore;
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::fmt::Write;
+use std::io::BufRead;
+use uuid::Uuid;
+
+const GENERATED_PASSWORD_LENGTH: usize = 24;
+
+#[derive(Parser)]
+#[command(name = "kobo", about = "A newsletter delivery service")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the HTTP server (the default).
+    Serve,
+    /// Manage editors without touching the database by hand.
+    #[command(subcommand)]
+    Admin(AdminCommand),
+}
+
+#[derive(Subcommand)]
+pub enum AdminCommand {
+    /// Create an editor. A random password is generated and printed unless one is given.
+    CreateEditor {
+        username: String,
+        /// One of owner, publisher, author or viewer.
+        #[arg(long, default_value = "author")]
+        role: String,
+        /// Where password reset links are sent.
+        #[arg(long)]
+        email: Option<String>,
+        /// Read the password from the first line of standard input.
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Set the address an editor's password reset links are sent to.
+    SetEmail { username: String, email: String },
+    /// List every editor.
+    ListEditors,
+    /// Prevent an editor from logging in or using their API tokens, and log them out.
+    DisableEditor { username: String },
+    /// Let a disabled editor back in.
+    EnableEditor { username: String },
+    /// Delete an editor along with their sessions and API tokens.
+    DeleteEditor { username: String },
+    /// Set a new password for an editor and log them out everywhere.
+    ResetPassword {
+        username: String,
+        /// Read the password from the first line of standard input.
+        #[arg(long)]
+        password_stdin: bool,
+    },
+}
+
+/// Run an admin command, returning what should be printed to the operator.
+pub async fn run_admin_command(
+    command: AdminCommand,
+    pool: &PgPool,
+    policy: &PasswordHashingPolicy,
+) -> Result<String, anyhow::Error> {
+    match command {
+        AdminCommand::CreateEditor {
+            username,
+            role,
+            email,
+            password_stdin,
+        } => {
+            let role = Role::try_from(role.as_str()).map_err(anyhow::Error::msg)?;
+            let email = email
+                .map(|email| SubscriberEmail::parse(&email))
+                .transpose()
+                .map_err(anyhow::Error::msg)?;
+            let (password, generated) = password(password_stdin)?;
+            let password_hash = policy.compute_password_hash(password.clone())?;
+            sqlx::query!(
+                r#"
+                INSERT INTO editors (user_id, username, password_hash, role, email)
+                VALUES ($1, $2, $3, $4, $5)
+                "#,
+                Uuid::new_v4(),
+                username,
+                password_hash.expose_secret(),
+                role.as_str(),
+                email.as_ref().map(|e| e.as_ref())
+            )
+            .execute(pool)
+            .await
+            .with_context(|| format!("Failed to create editor '{}'", username))?;
+            let mut output = format!("Created {} '{}'.\n", role, username);
+            if generated {
+                writeln!(output, "Password: {}", password.expose_secret())?;
+            }
+            Ok(output)
+        }
+        AdminCommand::SetEmail { username, email } => {
+            let email = SubscriberEmail::parse(&email).map_err(anyhow::Error::msg)?;
+            let user_id = find_editor(pool, &username).await?;
+            sqlx::query!(
+                r#"UPDATE editors SET email = $1 WHERE user_id = $2"#,
+                email.as_ref(),
+                user_id
+            )
+            .execute(pool)
+            .await
+            .context("Failed to set the editor's email")?;
+            Ok(format!("Set the email of '{}'.\n", username))
+        }
+        AdminCommand::ListEditors => {
+            let editors = sqlx::query!(
+                r#"
+                SELECT user_id, username, role, totp_enabled, disabled_at
+                FROM editors
+                ORDER BY username
+                "#
+            )
+            .fetch_all(pool)
+            .await
+            .context("Failed to list editors")?;
+            let mut output = String::new();
+            for editor in editors {
+                writeln!(
+                    output,
+                    "{}\t{}\t{}\t2fa:{}\t{}",
+                    editor.user_id,
+                    editor.username,
+                    editor.role,
+                    if editor.totp_enabled { "on" } else { "off" },
+                    if editor.disabled_at.is_some() {
+                        "disabled"
+                    } else {
+                        "active"
+                    }
+                )?;
+            }
+            Ok(output)
+        }
+        AdminCommand::DisableEditor { username } => {
+            let user_id = find_editor(pool, &username).await?;
+            sqlx::query!(
+                r#"UPDATE editors SET disabled_at = now() WHERE user_id = $1 AND disabled_at IS NULL"#,
+                user_id
+            )
+            .execute(pool)
+            .await
+            .context("Failed to disable the editor")?;
+            PgSessionStore::new(pool.clone())
+                .invalidate_user_sessions(user_id)
+                .await?;
+            Ok(format!("Disabled '{}'.\n", username))
+        }
+        AdminCommand::EnableEditor { username } => {
+            let user_id = find_editor(pool, &username).await?;
+            sqlx::query!(
+                r#"UPDATE editors SET disabled_at = NULL WHERE user_id = $1"#,
+                user_id
+            )
+            .execute(pool)
+            .await
+            .context("Failed to enable the editor")?;
+            Ok(format!("Enabled '{}'.\n", username))
+        }
+        AdminCommand::DeleteEditor { username } => {
+            let user_id = find_editor(pool, &username).await?;
+            sqlx::query!(r#"DELETE FROM editors WHERE user_id = $1"#, user_id)
+                .execute(pool)
+                .await
+                .context("Failed to delete the editor")?;
+            Ok(format!("Deleted '{}'.\n", username))
+        }
+        AdminCommand::ResetPassword {
+            username,
+            password_stdin,
+        } => {
+            let user_id = find_editor(pool, &username).await?;
+            let (password, generated) = password(password_stdin)?;
+            crate::authentication::change_password(user_id, password.clone(), policy, pool).await?;
+            PgSessionStore::new(pool.clone())
+                .invalidate_user_sessions(user_id)
+                .await?;
+            let mut output = format!("Reset the password of '{}'.\n", username);
+            if generated {
+                writeln!(output, "Password: {}", password.expose_secret())?;
+            }
+            Ok(output)
+        }
+    }
+}
+
+async fn find_editor(pool: &PgPool, username: &str) -> Result<Uuid, anyhow::Error> {
+    sqlx::query!(
+        r#"SELECT user_id FROM editors WHERE username = $1"#,
+        username
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the editor")?
+    .map(|row| row.user_id)
+    .with_context(|| format!("There is no editor named '{}'", username))
+}
+
+/// The password to set, and whether we generated it (and thus must show it to the operator).
+fn password(from_stdin: bool) -> Result<(Secret<String>, bool), anyhow::Error> {
+    if from_stdin {
+        Ok((read_password(&mut std::io::stdin().lock())?, false))
+    } else {
+        let password = rand::thread_rng()
+            .sample_iter(Alphanumeric)
+            .map(char::from)
+            .take(GENERATED_PASSWORD_LENGTH)
+            .collect();
+        Ok((Secret::new(password), true))
+    }
+}
+
+/// Read a password from the first line of `input`, held to the same rules as in the admin panel.
+fn read_password(input: &mut impl BufRead) -> Result<Secret<String>, anyhow::Error> {
+    let mut line = String::new();
+    input
+        .read_line(&mut line)
+        .context("Failed to read the password from standard input")?;
+    let password = Secret::new(line.trim_end_matches(&['\r', '\n'][..]).to_string());
+    check_password_length(&password).map_err(anyhow::Error::msg)?;
+    Ok(password)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_password;
+    use claim::{assert_err, assert_ok};
+    use secrecy::ExposeSecret;
+
+    #[test]
+    fn passwords_from_standard_input_must_have_a_reasonable_length() {
+        assert_err!(read_password(&mut "".as_bytes()));
+        assert_err!(read_password(&mut "too-short\n".as_bytes()));
+        assert_err!(read_password(
+            &mut format!("{}\n", "a".repeat(129)).as_bytes()
+        ));
+    }
+
+    #[test]
+    fn passwords_are_read_from_the_first_line_of_standard_input() {
+        let password = assert_ok!(read_password(&mut "correct horse\r\nbattery\n".as_bytes()));
+        assert_eq!(password.expose_secret(), "correct horse");
+    }
+}
@@ -1,6 +1,8 @@
 //! src/configuration.rs
 
+use crate::authentication::PasswordHashingPolicy;
 use crate::domain::SubscriberEmail;
+use crate::issue_delivery_worker::RetryPolicy;
 
 use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
@@ -12,6 +14,13 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub login_throttling: LoginThrottlingSettings,
+    pub idempotency: IdempotencySettings,
+    pub issue_delivery: IssueDeliverySettings,
+    pub issue_scheduling: IssueSchedulingSettings,
+    /// Single sign-on through an external identity provider, off unless configured.
+    pub oidc: Option<OidcSettings>,
 }
 
 #[derive(Deserialize)]
@@ -28,6 +37,26 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    pub totp_encryption_key: Secret<String>,
+    pub session_ttl_minutes: i64,
+    /// How often expired sessions are deleted.
+    pub session_expiry_interval_minutes: u64,
+    pub password_reset_ttl_minutes: u64,
+}
+
+impl ApplicationSettings {
+    pub fn session_ttl(&self) -> actix_web::cookie::time::Duration {
+        actix_web::cookie::time::Duration::minutes(self.session_ttl_minutes)
+    }
+
+    pub fn session_expiry_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.session_expiry_interval_minutes * 60)
+    }
+
+    pub fn password_reset_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.password_reset_ttl_minutes * 60)
+    }
 }
 
 #[derive(Deserialize)]
@@ -48,6 +77,161 @@ impl EmailClientSettings {
     }
 }
 
+#[derive(Deserialize)]
+pub struct PasswordHashingSettings {
+    pub algorithm: String,
+    pub version: u32,
+    pub memory_cost_kib: u32,
+    pub time_cost: u32,
+    pub parallelism: u32,
+}
+
+impl PasswordHashingSettings {
+    pub fn policy(&self) -> Result<PasswordHashingPolicy, anyhow::Error> {
+        let algorithm = self
+            .algorithm
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid password hashing algorithm: {}", e))?;
+        let version = self
+            .version
+            .try_into()
+            .map_err(|e| anyhow::anyhow!("Invalid password hashing version: {}", e))?;
+        let params =
+            argon2::Params::new(self.memory_cost_kib, self.time_cost, self.parallelism, None)
+                .map_err(|e| anyhow::anyhow!("Invalid password hashing parameters: {}", e))?;
+        PasswordHashingPolicy::new(algorithm, version, params)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LoginThrottlingSettings {
+    pub max_failed_attempts_per_username: i32,
+    pub max_failed_attempts_per_client_ip: i32,
+    /// Password reset requests are throttled like failed logins, but counted separately.
+    pub max_password_resets_per_username: i32,
+    pub max_password_resets_per_client_ip: i32,
+    pub base_lockout_seconds: u64,
+    pub max_lockout_seconds: u64,
+    pub client_ip_source: ClientIpSource,
+}
+
+/// Where the client IP that failed attempts are also counted against comes from.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientIpSource {
+    /// The address of the connection. Only meaningful when the app is exposed directly: behind
+    /// a reverse proxy every client shares the proxy's address, and one of them could lock
+    /// everybody else out.
+    PeerAddress,
+    /// The `Forwarded` or `X-Forwarded-For` header. Clients can forge it, so only use this
+    /// behind a reverse proxy that overwrites it.
+    ForwardedHeader,
+    /// Only count failures per username.
+    Disabled,
+}
+
+impl LoginThrottlingSettings {
+    pub fn base_lockout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.base_lockout_seconds)
+    }
+
+    pub fn max_lockout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.max_lockout_seconds)
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct IdempotencySettings {
+    /// How long a response is replayed for; afterwards its key may be reused.
+    pub key_ttl_hours: u64,
+    pub expiry_interval_minutes: u64,
+}
+
+impl IdempotencySettings {
+    pub fn key_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.key_ttl_hours * 60 * 60)
+    }
+
+    pub fn expiry_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.expiry_interval_minutes * 60)
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct IssueDeliverySettings {
+    /// How many workers drain the queue concurrently.
+    pub workers: usize,
+    /// How many queued deliveries a worker claims at once.
+    pub batch_size: i64,
+    /// How many emails of a batch a worker sends at once.
+    pub send_concurrency: usize,
+    /// How long claimed deliveries are hidden from other workers. Those a worker didn't get to
+    /// record, e.g. because it crashed, are picked up again once this has elapsed.
+    pub claim_ttl_seconds: u64,
+    /// How long idle workers wait before checking the queue again.
+    pub poll_interval_millis: u64,
+    /// How many times a delivery failing for a transient reason is retried before giving up.
+    pub max_retries: i16,
+    pub base_retry_delay_millis: u64,
+    pub max_retry_delay_millis: u64,
+}
+
+impl IssueDeliverySettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.poll_interval_millis)
+    }
+
+    pub fn claim_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.claim_ttl_seconds)
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: self.max_retries,
+            base_delay: std::time::Duration::from_millis(self.base_retry_delay_millis),
+            max_delay: std::time::Duration::from_millis(self.max_retry_delay_millis),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct IssueSchedulingSettings {
+    /// How often the scheduler looks for scheduled issues that are due.
+    pub poll_interval_millis: u64,
+}
+
+impl IssueSchedulingSettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.poll_interval_millis)
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct OidcSettings {
+    /// Shown on the login page, as in "Sign in with <provider_name>".
+    pub provider_name: String,
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: Secret<String>,
+    /// Link identities to the existing editor with the same verified email address.
+    ///
+    /// Only enable this if the provider is the authority on those addresses: anyone holding an
+    /// account there with an editor's email could sign in as that editor.
+    pub allow_email_linking: bool,
+    /// Create an editor on first sign-in for identities that don't match an existing one.
+    pub allow_jit_provisioning: bool,
+    /// The role given to editors created on first sign-in.
+    pub jit_role: String,
+    /// How long the provider's metadata and signing keys are reused before discovering it again.
+    pub discovery_ttl_minutes: u64,
+}
+
+impl OidcSettings {
+    pub fn discovery_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.discovery_ttl_minutes * 60)
+    }
+}
+
 impl DatabaseSettings {
     pub fn with_db(&self) -> PgConnectOptions {
         let mut options = self.without_db().database(&self.database_name);
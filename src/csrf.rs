@@ -0,0 +1,227 @@
+use crate::routes::DRAFT_FORM_LIMIT;
+use crate::session_state::SESSION_COOKIE_NAME;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::cookie::{Cookie, CookieJar, Key, SameSite};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::PayloadError;
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::{mime, web, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use futures::StreamExt;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Deserialize;
+use std::cell::Cell;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+const CSRF_COOKIE_NAME: &str = "_csrf";
+const CSRF_FIELD_NAME: &str = "csrf_token";
+const CSRF_HEADER_NAME: &str = "X-CSRF-Token";
+const TOKEN_LENGTH: usize = 32;
+/// The limit of `web::Form`, which form bodies are held to unless their route raises it.
+const DEFAULT_FORM_LIMIT: usize = 16 * 1024;
+
+/// The key used to sign CSRF cookies, so that a third party can't plant a token of its choosing.
+pub struct CsrfKey(pub Key);
+
+/// The CSRF token of the current browser session, to be embedded in every form.
+#[derive(Clone)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    /// Render the token as a hidden form field.
+    pub fn to_html(&self) -> String {
+        format!(
+            "<input type=\"hidden\" name=\"{}\" value=\"{}\">",
+            CSRF_FIELD_NAME,
+            htmlescape::encode_attribute(&self.0)
+        )
+    }
+}
+
+impl FromRequest for CsrfToken {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<CsrfToken>().cloned().ok_or_else(|| {
+            actix_web::error::ErrorInternalServerError(
+                "Tried to get a CSRF token outside of `csrf_protection`",
+            )
+        }))
+    }
+}
+
+/// Whether the response must carry a new CSRF token, shared with the handler.
+///
+/// The session rotates the token on login and logout, so that a token planted beforehand, e.g.
+/// from a subdomain, stops working as soon as the session it could be used against starts.
+#[derive(Clone, Default)]
+pub struct CsrfRotation(Rc<Cell<bool>>);
+
+impl CsrfRotation {
+    /// Get the flag of the current request, or a detached one outside of `csrf_protection`.
+    pub fn of(req: &HttpRequest) -> Self {
+        req.extensions()
+            .get::<CsrfRotation>()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Replace the CSRF token of the browser along with the response.
+    pub fn rotate(&self) {
+        self.0.set(true);
+    }
+}
+
+#[derive(Deserialize)]
+struct CsrfFormField {
+    csrf_token: Option<String>,
+}
+
+/// Middleware implementing signed double-submit cookies.
+///
+/// Every browser gets a random token in a signed cookie, which pages embed in their forms via
+/// [`CsrfToken`]. Unsafe requests must then send it back, either as a `csrf_token` form field
+/// or in the `X-CSRF-Token` header: a cross-site form can't, since it can't read the cookie.
+///
+/// API calls are exempt, as long as they don't carry a session cookie: they authenticate with
+/// Basic or Bearer credentials rather than cookies, or send JSON, which browsers won't send
+/// cross-site without a CORS preflight. Whenever a session cookie could authenticate the
+/// request, the token is required. It is rotated on login and logout, see [`CsrfRotation`].
+pub async fn csrf_protection(
+    key: web::Data<CsrfKey>,
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let existing = req
+        .cookie(CSRF_COOKIE_NAME)
+        .and_then(|cookie| verify_cookie(&key.0, cookie));
+
+    if !is_safe(req.method()) && !is_api_call(&req) {
+        let submitted = submitted_token(&mut req).await?;
+        let valid = match (&existing, &submitted) {
+            (Some(expected), Some(submitted)) => expected == submitted,
+            _ => false,
+        };
+        if !valid {
+            tracing::warn!("Rejected a request with a missing or invalid CSRF token");
+            let response = HttpResponse::Forbidden().body("Missing or invalid CSRF token.");
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    }
+
+    let token = existing.clone().unwrap_or_else(generate_token);
+    let rotation = CsrfRotation::default();
+    req.extensions_mut().insert(CsrfToken(token.clone()));
+    req.extensions_mut().insert(rotation.clone());
+    let mut response = next.call(req).await?;
+    let rotate = rotation.0.get();
+    if rotate || existing.is_none() {
+        let token = if rotate { generate_token() } else { token };
+        response
+            .response_mut()
+            .add_cookie(&signed_cookie(&key.0, token))?;
+    }
+    Ok(response.map_into_left_body())
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+fn is_api_call(req: &ServiceRequest) -> bool {
+    if req.cookie(SESSION_COOKIE_NAME).is_some() {
+        return false;
+    }
+    let has_api_credentials = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("Basic ") || value.starts_with("Bearer "))
+        .unwrap_or(false);
+    let is_json = req
+        .mime_type()
+        .ok()
+        .flatten()
+        .map(|content_type| content_type.essence_str() == mime::APPLICATION_JSON.essence_str())
+        .unwrap_or(false);
+    has_api_credentials || is_json
+}
+
+/// Look for the token in the header first, then in the form body, which is put back for the
+/// handler to read.
+async fn submitted_token(req: &mut ServiceRequest) -> Result<Option<String>, actix_web::Error> {
+    if let Some(value) = req.headers().get(CSRF_HEADER_NAME) {
+        return Ok(value.to_str().ok().map(str::to_string));
+    }
+    let is_form = req
+        .mime_type()
+        .ok()
+        .flatten()
+        .map(|content_type| {
+            content_type.essence_str() == mime::APPLICATION_WWW_FORM_URLENCODED.essence_str()
+        })
+        .unwrap_or(false);
+    if !is_form {
+        return Ok(None);
+    }
+    let body = read_form_body(req).await?;
+    let token = serde_urlencoded::from_bytes::<CsrfFormField>(&body)
+        .ok()
+        .and_then(|field| field.csrf_token);
+    req.set_payload(Payload::from(body));
+    Ok(token)
+}
+
+/// Buffer a form body, up to the limit the form extractor of its route enforces: this runs before
+/// routing, so it can't rely on the `FormConfig` of the route.
+async fn read_form_body(req: &mut ServiceRequest) -> Result<web::Bytes, actix_web::Error> {
+    let limit = if req.path().starts_with("/admin/drafts") {
+        DRAFT_FORM_LIMIT
+    } else {
+        DEFAULT_FORM_LIMIT
+    };
+    let mut payload = req.take_payload();
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > limit {
+            return Err(PayloadError::Overflow.into());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.freeze())
+}
+
+fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(Alphanumeric)
+        .map(char::from)
+        .take(TOKEN_LENGTH)
+        .collect()
+}
+
+fn signed_cookie(key: &Key, token: String) -> Cookie<'static> {
+    let cookie = Cookie::build(CSRF_COOKIE_NAME, token)
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .finish();
+    let mut jar = CookieJar::new();
+    jar.signed_mut(key).add(cookie);
+    jar.get(CSRF_COOKIE_NAME)
+        .cloned()
+        .expect("The signed CSRF cookie was just added to the jar")
+}
+
+fn verify_cookie(key: &Key, cookie: Cookie<'static>) -> Option<String> {
+    let mut jar = CookieJar::new();
+    jar.add_original(cookie);
+    let cookie = jar.signed(key).get(CSRF_COOKIE_NAME)?;
+    Some(cookie.value().to_string())
+}
@@ -1,7 +1,11 @@
 mod new_subscriber;
+mod newsletter_content;
+mod newsletter_template;
 mod subscriber_email;
 mod subscriber_name;
 
 pub use new_subscriber::NewSubscriber;
+pub use newsletter_content::NewsletterContent;
+pub use newsletter_template::{NewsletterTemplate, Recipient, TemplateVariable};
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
@@ -0,0 +1,362 @@
+use crate::domain::{NewsletterTemplate, TemplateVariable};
+use pulldown_cmark::{html, Event, Options, Parser, Tag};
+use std::sync::{Arc, Mutex};
+
+/// The body of an issue, written in Markdown and rendered to both email formats.
+///
+/// Template variables are kept as they are in both renderings, to be filled in for each recipient.
+#[derive(Debug)]
+pub struct NewsletterContent {
+    markdown: String,
+    html: String,
+    text: String,
+}
+
+impl NewsletterContent {
+    /// The longest Markdown an issue may have, in bytes.
+    pub const MAX_LENGTH: usize = 256 * 1024;
+
+    pub fn parse(markdown: &str) -> Result<Self, String> {
+        if markdown.trim().is_empty() {
+            return Err("The content of an issue cannot be empty.".into());
+        }
+        if markdown.len() > Self::MAX_LENGTH {
+            return Err(format!(
+                "The content of an issue cannot be longer than {} KiB.",
+                Self::MAX_LENGTH / 1024
+            ));
+        }
+        // Variables are swapped for plain words while rendering, which would otherwise mangle
+        // them, e.g. in link destinations.
+        let source = NewsletterTemplate::parse(markdown)?.fill(placeholder);
+        Ok(Self {
+            markdown: markdown.to_string(),
+            html: restore_variables(render_html(&source)?),
+            text: restore_variables(render_text(&source)),
+        })
+    }
+
+    pub fn markdown(&self) -> &str {
+        &self.markdown
+    }
+
+    /// Sanitized, so that raw HTML in the Markdown can't smuggle scripts into the email.
+    pub fn html(&self) -> &str {
+        &self.html
+    }
+
+    /// Links are numbered in the text and listed at the end, like footnotes.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+fn placeholder(variable: TemplateVariable) -> String {
+    format!(
+        "KOBOTEMPLATEVARIABLE{}",
+        variable.as_str().replace('_', "").to_uppercase()
+    )
+}
+
+fn restore_variables(mut rendered: String) -> String {
+    for variable in TemplateVariable::ALL {
+        rendered = rendered.replace(&placeholder(variable), &variable.to_string());
+    }
+    rendered
+}
+
+fn parser(markdown: &str) -> Parser<'_, '_> {
+    Parser::new_ext(markdown, Options::ENABLE_STRIKETHROUGH)
+}
+
+/// Subscribers choose their own name and email address, which are filled in after sanitizing:
+/// they must not end up in a link, where they could smuggle in e.g. a `javascript:` URL.
+fn render_html(markdown: &str) -> Result<String, String> {
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, parser(markdown));
+    let misplaced = Arc::new(Mutex::new(None));
+    let found = misplaced.clone();
+    let html = ammonia::Builder::default()
+        .attribute_filter(move |element, attribute, value| {
+            if is_url_attribute(element, attribute) {
+                let variable = [TemplateVariable::Name, TemplateVariable::Email]
+                    .into_iter()
+                    .find(|variable| value.contains(&placeholder(*variable)));
+                if let Some(variable) = variable {
+                    found.lock().unwrap().get_or_insert(variable);
+                }
+            }
+            Some(value.into())
+        })
+        .clean(&unsafe_html)
+        .to_string();
+    let misplaced = *misplaced.lock().unwrap();
+    match misplaced {
+        Some(variable) => Err(format!(
+            "'{}' cannot be used in a link address, only '{}' can.",
+            variable,
+            TemplateVariable::UnsubscribeUrl
+        )),
+        None => Ok(html),
+    }
+}
+
+/// The attributes ammonia checks the URL scheme of.
+fn is_url_attribute(element: &str, attribute: &str) -> bool {
+    matches!(attribute, "href" | "src")
+        || (element == "form" && attribute == "action")
+        || (element == "object" && attribute == "data")
+        || (matches!(element, "button" | "input") && attribute == "formaction")
+        || (element == "a" && attribute == "ping")
+        || (element == "video" && attribute == "poster")
+}
+
+fn render_text(markdown: &str) -> String {
+    let mut renderer = TextRenderer::default();
+    for event in parser(markdown) {
+        renderer.handle(event);
+    }
+    renderer.finish()
+}
+
+#[derive(Default)]
+struct TextRenderer {
+    output: String,
+    at_line_start: bool,
+    /// Written at the start of each line: quote markers and the indentation of lists and code.
+    prefixes: Vec<String>,
+    /// The next number of each open list, `None` for bulleted lists.
+    lists: Vec<Option<u64>>,
+    /// The destination of each open link, with where its text starts in the output.
+    open_links: Vec<(String, usize)>,
+    links: Vec<String>,
+}
+
+impl TextRenderer {
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) | Event::Code(text) => self.write(&text),
+            Event::SoftBreak | Event::HardBreak => self.write("\n"),
+            Event::Rule => {
+                self.write("----------");
+                self.blank_line();
+            }
+            // Raw HTML has no plain text equivalent.
+            Event::Html(_) => {}
+            Event::FootnoteReference(_) | Event::TaskListMarker(_) => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::BlockQuote => self.prefixes.push("> ".into()),
+            Tag::CodeBlock(_) => self.prefixes.push("    ".into()),
+            Tag::List(start) => {
+                self.end_line();
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.end_line();
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "- ".into(),
+                };
+                self.write(&marker);
+                self.prefixes.push(" ".repeat(marker.len()));
+            }
+            Tag::Link(_, destination, _) | Tag::Image(_, destination, _) => {
+                self.open_links
+                    .push((destination.to_string(), self.output.len()));
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph | Tag::Heading(..) => self.blank_line(),
+            Tag::BlockQuote | Tag::CodeBlock(_) => {
+                self.prefixes.pop();
+                self.blank_line();
+            }
+            Tag::List(_) => {
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.blank_line();
+                }
+            }
+            Tag::Item => {
+                self.prefixes.pop();
+                self.end_line();
+            }
+            Tag::Link(..) | Tag::Image(..) => {
+                if let Some((destination, text_start)) = self.open_links.pop() {
+                    self.add_footnote(destination, text_start);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Links whose text is the address itself are readable as they are.
+    fn add_footnote(&mut self, destination: String, text_start: usize) {
+        if self.output[text_start..] == destination {
+            return;
+        }
+        let number = match self.links.iter().position(|link| link == &destination) {
+            Some(index) => index + 1,
+            None => {
+                self.links.push(destination);
+                self.links.len()
+            }
+        };
+        self.write(&format!(" [{}]", number));
+    }
+
+    fn write(&mut self, text: &str) {
+        for c in text.chars() {
+            if self.at_line_start && c != '\n' {
+                let prefix = self.prefixes.concat();
+                self.output.push_str(&prefix);
+            }
+            self.output.push(c);
+            self.at_line_start = c == '\n';
+        }
+    }
+
+    fn end_line(&mut self) {
+        if !self.output.is_empty() && !self.at_line_start {
+            self.write("\n");
+        }
+    }
+
+    fn blank_line(&mut self) {
+        self.end_line();
+        if !self.output.is_empty() && !self.output.ends_with("\n\n") {
+            self.write("\n");
+        }
+    }
+
+    fn finish(self) -> String {
+        let mut text = self.output.trim_end().to_string();
+        if !self.links.is_empty() {
+            text.push_str("\n\n");
+            for (index, link) in self.links.iter().enumerate() {
+                text.push_str(&format!("[{}] {}\n", index + 1, link));
+            }
+        }
+        text.trim_end().to_string() + "\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::{assert_err, assert_ok};
+
+    fn parse(markdown: &str) -> NewsletterContent {
+        NewsletterContent::parse(markdown).unwrap()
+    }
+
+    #[test]
+    fn empty_content_is_rejected() {
+        assert_err!(NewsletterContent::parse(""));
+        assert_err!(NewsletterContent::parse(" \n "));
+    }
+
+    #[test]
+    fn content_longer_than_the_limit_is_rejected() {
+        let markdown = "a".repeat(NewsletterContent::MAX_LENGTH);
+        assert_ok!(NewsletterContent::parse(&markdown));
+        assert_err!(NewsletterContent::parse(&(markdown + "a")));
+    }
+
+    #[test]
+    fn markdown_is_rendered_to_html() {
+        let content = parse("# Title\n\nSome *emphasis* and a [link](https://example.com).");
+        assert_eq!(
+            content.html(),
+            "<h1>Title</h1>\n<p>Some <em>emphasis</em> and a \
+             <a href=\"https://example.com\" rel=\"noopener noreferrer\">link</a>.</p>\n"
+        );
+        assert_eq!(
+            content.markdown(),
+            "# Title\n\nSome *emphasis* and a [link](https://example.com)."
+        );
+    }
+
+    #[test]
+    fn template_variables_survive_rendering() {
+        let content = parse("Hi {{ name }}, [unsubscribe]({{ unsubscribe_url }}) at any time.");
+        assert_eq!(
+            content.html(),
+            "<p>Hi {{ name }}, <a href=\"{{ unsubscribe_url }}\" rel=\"noopener noreferrer\">\
+             unsubscribe</a> at any time.</p>\n"
+        );
+        assert_eq!(
+            content.text(),
+            "Hi {{ name }}, unsubscribe [1] at any time.\n\n[1] {{ unsubscribe_url }}\n"
+        );
+        assert_err!(NewsletterContent::parse("Hi {{ nmae }}"));
+    }
+
+    #[test]
+    fn subscriber_details_cannot_be_used_in_link_addresses() {
+        for markdown in [
+            "[click]({{ name }})",
+            "[click](https://example.com/?to={{ email }})",
+            "![avatar]({{ name }})",
+            "<a href=\"{{ email }}\">click</a>",
+        ] {
+            assert_err!(NewsletterContent::parse(markdown));
+        }
+        let error = NewsletterContent::parse("[click]({{ name }})").unwrap_err();
+        assert_eq!(
+            error,
+            "'{{ name }}' cannot be used in a link address, only '{{ unsubscribe_url }}' can."
+        );
+        // They can still be the text of a link.
+        assert_ok!(NewsletterContent::parse(
+            "[{{ name }}]({{ unsubscribe_url }})"
+        ));
+    }
+
+    #[test]
+    fn scripts_are_stripped_from_the_html() {
+        let content = parse("Hello <script>alert('hi')</script>\n\n[x](javascript:alert(1))");
+        assert!(!content.html().contains("script"));
+        assert!(!content.html().contains("javascript"));
+        assert_ok!(NewsletterContent::parse("<script>alert('hi')</script>"));
+    }
+
+    #[test]
+    fn links_become_footnotes_in_plain_text() {
+        let content = parse(
+            "Read [the post](https://example.com/post) and [the docs](https://example.com/docs).\n\n\
+             The [post](https://example.com/post) again, and <https://example.com>.",
+        );
+        assert_eq!(
+            content.text(),
+            "Read the post [1] and the docs [2].\n\n\
+             The post [1] again, and https://example.com.\n\n\
+             [1] https://example.com/post\n\
+             [2] https://example.com/docs\n"
+        );
+    }
+
+    #[test]
+    fn blocks_stay_readable_in_plain_text() {
+        let content = parse(
+            "## Agenda\n\n- one\n- two\n  1. nested\n  2. list\n\n> quoted\n> text\n\n    let code = 1;\n",
+        );
+        assert_eq!(
+            content.text(),
+            "Agenda\n\n- one\n- two\n  1. nested\n  2. list\n\n> quoted\n> text\n\n    let code = 1;\n"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use std::fmt::{Display, Formatter};
+
+/// A variable of the template language, filled in with the details of each recipient.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TemplateVariable {
+    Name,
+    Email,
+    UnsubscribeUrl,
+}
+
+impl TemplateVariable {
+    pub const ALL: [TemplateVariable; 3] = [
+        TemplateVariable::Name,
+        TemplateVariable::Email,
+        TemplateVariable::UnsubscribeUrl,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TemplateVariable::Name => "name",
+            TemplateVariable::Email => "email",
+            TemplateVariable::UnsubscribeUrl => "unsubscribe_url",
+        }
+    }
+}
+
+impl Display for TemplateVariable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{{{ {} }}}}", self.as_str())
+    }
+}
+
+/// The values of the template variables for one recipient.
+pub struct Recipient<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    pub unsubscribe_url: &'a str,
+}
+
+impl Recipient<'_> {
+    fn value(&self, variable: TemplateVariable) -> &str {
+        match variable {
+            TemplateVariable::Name => self.name,
+            TemplateVariable::Email => self.email,
+            TemplateVariable::UnsubscribeUrl => self.unsubscribe_url,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Segment {
+    Text(String),
+    Variable(TemplateVariable),
+}
+
+/// A subject or body with `{{ variable }}` placeholders.
+///
+/// Parsing rejects unknown variables, so that a typo is caught when an issue is written rather
+/// than when it is being sent.
+#[derive(Debug)]
+pub struct NewsletterTemplate(Vec<Segment>);
+
+impl NewsletterTemplate {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| "A template variable is missing its closing '}}'.".to_string())?;
+            let name = rest[start + 2..start + end].trim();
+            let variable = TemplateVariable::ALL
+                .into_iter()
+                .find(|variable| variable.as_str() == name)
+                .ok_or_else(|| {
+                    format!(
+                        "'{}' is not a template variable, use '{}', '{}' or '{}'.",
+                        &rest[start..start + end + 2],
+                        TemplateVariable::Name,
+                        TemplateVariable::Email,
+                        TemplateVariable::UnsubscribeUrl
+                    )
+                })?;
+            segments.push(Segment::Variable(variable));
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+        Ok(Self(segments))
+    }
+
+    /// Fill in the variables with `value`.
+    pub fn fill(&self, value: impl Fn(TemplateVariable) -> String) -> String {
+        self.0
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text.clone(),
+                Segment::Variable(variable) => value(*variable),
+            })
+            .collect()
+    }
+
+    /// Render a subject or a plain text body.
+    pub fn render(&self, recipient: &Recipient) -> String {
+        self.fill(|variable| recipient.value(variable).to_string())
+    }
+
+    /// Render an HTML body, escaping the values: they may end up in quoted attributes, like links.
+    pub fn render_html(&self, recipient: &Recipient) -> String {
+        self.fill(|variable| {
+            htmlescape::encode_minimal(recipient.value(variable))
+                .replace('"', "&quot;")
+                .replace('\'', "&#x27;")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claim::assert_err;
+
+    fn recipient() -> Recipient<'static> {
+        Recipient {
+            name: "Ursula",
+            email: "ursula_le_guin@gmail.com",
+            unsubscribe_url: "https://example.com/unsubscribe?token=abc&x=1",
+        }
+    }
+
+    #[test]
+    fn variables_are_filled_in_for_the_recipient() {
+        let template =
+            NewsletterTemplate::parse("Hi {{ name }} ({{email}}), {{  unsubscribe_url }}").unwrap();
+        assert_eq!(
+            template.render(&recipient()),
+            "Hi Ursula (ursula_le_guin@gmail.com), https://example.com/unsubscribe?token=abc&x=1"
+        );
+    }
+
+    #[test]
+    fn values_are_escaped_in_html() {
+        let template =
+            NewsletterTemplate::parse("<a href=\"{{ unsubscribe_url }}\">{{ name }}</a>").unwrap();
+        let recipient = Recipient {
+            name: "<b>\"Ursula\"</b>",
+            ..recipient()
+        };
+        assert_eq!(
+            template.render_html(&recipient),
+            "<a href=\"https://example.com/unsubscribe?token=abc&amp;x=1\">\
+             &lt;b&gt;&quot;Ursula&quot;&lt;/b&gt;</a>"
+        );
+    }
+
+    #[test]
+    fn text_without_variables_is_left_alone() {
+        let template = NewsletterTemplate::parse("No variables { here }").unwrap();
+        assert_eq!(template.render(&recipient()), "No variables { here }");
+    }
+
+    #[test]
+    fn unknown_variables_are_rejected() {
+        let error = NewsletterTemplate::parse("Hi {{ nme }}").unwrap_err();
+        assert_eq!(
+            error,
+            "'{{ nme }}' is not a template variable, \
+             use '{{ name }}', '{{ email }}' or '{{ unsubscribe_url }}'."
+        );
+    }
+
+    #[test]
+    fn unclosed_variables_are_rejected() {
+        assert_err!(NewsletterTemplate::parse("Hi {{ name"));
+        assert_err!(NewsletterTemplate::parse("Hi {{ name }"));
+    }
+}
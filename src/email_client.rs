@@ -1,5 +1,5 @@
 use crate::domain::SubscriberEmail;
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use secrecy::{ExposeSecret, Secret};
 use serde::Serialize;
 
@@ -13,6 +13,35 @@ struct SendEmailRequest<'a> {
     text_body: &'a str,
 }
 
+/// Why an email could not be sent, and thus whether trying again may help.
+#[derive(thiserror::Error)]
+pub enum EmailError {
+    /// Timeouts, connection failures, rate limiting and server errors: worth retrying later.
+    #[error("Failed to send an email, it may succeed later.")]
+    Transient(#[source] reqwest::Error),
+    /// The email API refused the email itself, e.g. because the recipient is invalid.
+    #[error("The email was rejected.")]
+    Permanent(#[source] reqwest::Error),
+}
+
+impl std::fmt::Debug for EmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        crate::routes::error_chain_fmt(self, f)
+    }
+}
+
+impl From<reqwest::Error> for EmailError {
+    fn from(e: reqwest::Error) -> Self {
+        match e.status() {
+            Some(status) if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS => {
+                Self::Permanent(e)
+            }
+            _ => Self::Transient(e),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct EmailClient {
     client: Client,
     base_url: reqwest::Url,
@@ -44,7 +73,7 @@ impl EmailClient {
         subject_content: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
+    ) -> Result<(), EmailError> {
         let url = self
             .base_url
             .join("{}/email")
@@ -70,7 +99,7 @@ impl EmailClient {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use claim::{assert_err, assert_ok};
+    use claim::{assert_err, assert_matches, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
     use fake::{Fake, Faker};
@@ -169,6 +198,42 @@ mod tests {
         assert_err!(outcome);
     }
 
+    #[tokio::test]
+    async fn server_errors_and_rate_limiting_are_transient() {
+        for status in [500, 503, 429] {
+            let mock_server = MockServer::start().await;
+            let email_client = email_client(&mock_server.uri());
+            Mock::given(any())
+                .respond_with(ResponseTemplate::new(status))
+                .expect(1)
+                .mount(&mock_server)
+                .await;
+
+            let outcome = email_client
+                .send_email(&email(), &sentence(), &content(), &content())
+                .await;
+            assert_matches!(outcome, Err(EmailError::Transient(_)));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_emails_are_permanent_failures() {
+        for status in [400, 422] {
+            let mock_server = MockServer::start().await;
+            let email_client = email_client(&mock_server.uri());
+            Mock::given(any())
+                .respond_with(ResponseTemplate::new(status))
+                .expect(1)
+                .mount(&mock_server)
+                .await;
+
+            let outcome = email_client
+                .send_email(&email(), &sentence(), &content(), &content())
+                .await;
+            assert_matches!(outcome, Err(EmailError::Permanent(_)));
+        }
+    }
+
     #[tokio::test]
     async fn send_email_times_out_if_the_server_takes_too_long() {
         let mock_server = MockServer::start().await;
@@ -184,6 +249,6 @@ mod tests {
         let outcome = email_client
             .send_email(&email(), &sentence(), &content(), &content())
             .await;
-        assert_err!(outcome);
+        assert_matches!(outcome, Err(EmailError::Transient(_)));
     }
 }
@@ -0,0 +1,177 @@
+use actix_web::body::MessageBody;
+use actix_web::cookie::{Cookie, CookieJar, Key, SameSite};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+const FLASH_COOKIE_NAME: &str = "_flash";
+
+tokio::task_local! {
+    static OUTGOING_MESSAGES: Rc<RefCell<Vec<FlashMessage>>>;
+}
+
+/// The key used to sign flash message cookies, so that they can't be forged by a third party.
+pub struct FlashMessagesKey(pub Key);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Info => "info",
+            Level::Warning => "warning",
+            Level::Error => "error",
+        }
+    }
+}
+
+/// A one-shot message shown to the user on the next page they load, typically after a redirect.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FlashMessage {
+    level: Level,
+    content: String,
+}
+
+impl FlashMessage {
+    pub fn new(level: Level, content: impl Into<String>) -> Self {
+        Self {
+            level,
+            content: content.into(),
+        }
+    }
+
+    pub fn info(content: impl Into<String>) -> Self {
+        Self::new(Level::Info, content)
+    }
+
+    pub fn warning(content: impl Into<String>) -> Self {
+        Self::new(Level::Warning, content)
+    }
+
+    pub fn error(content: impl Into<String>) -> Self {
+        Self::new(Level::Error, content)
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// Queue the message to be attached to the response of the current request.
+    pub fn send(self) {
+        let outcome = OUTGOING_MESSAGES.try_with(|messages| messages.borrow_mut().push(self));
+        if outcome.is_err() {
+            tracing::error!("Tried to send a flash message outside of `flash_messages_framework`");
+        }
+    }
+}
+
+/// The flash messages that came in with the current request.
+#[derive(Clone, Default)]
+pub struct IncomingFlashMessages(Vec<FlashMessage>);
+
+impl IncomingFlashMessages {
+    pub fn iter(&self) -> impl Iterator<Item = &FlashMessage> {
+        self.0.iter()
+    }
+
+    /// Render the messages as HTML, ready to be embedded in a page.
+    pub fn to_html(&self) -> String {
+        self.0
+            .iter()
+            .map(|m| {
+                format!(
+                    "<p class=\"flash-{}\"><i>{}</i></p>\n",
+                    m.level.as_str(),
+                    htmlescape::encode_minimal(&m.content)
+                )
+            })
+            .collect()
+    }
+}
+
+impl FromRequest for IncomingFlashMessages {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let messages = req
+            .extensions()
+            .get::<IncomingFlashMessages>()
+            .cloned()
+            .unwrap_or_default();
+        ready(Ok(messages))
+    }
+}
+
+/// Middleware reading incoming flash messages from their signed cookie and storing the ones
+/// queued by the handler via [`FlashMessage::send`].
+///
+/// Messages are one-shot: the cookie is cleared once it has been read.
+pub async fn flash_messages_framework(
+    key: web::Data<FlashMessagesKey>,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let incoming_cookie = req.cookie(FLASH_COOKIE_NAME);
+    let incoming = incoming_cookie
+        .clone()
+        .and_then(|cookie| verify_cookie(&key.0, cookie))
+        .unwrap_or_default();
+    req.extensions_mut().insert(IncomingFlashMessages(incoming));
+
+    let outgoing = Rc::new(RefCell::new(Vec::new()));
+    let mut response = OUTGOING_MESSAGES
+        .scope(outgoing.clone(), next.call(req))
+        .await?;
+
+    let outgoing = outgoing.take();
+    if !outgoing.is_empty() {
+        let cookie = signed_cookie(&key.0, &outgoing)?;
+        response.response_mut().add_cookie(&cookie)?;
+    } else if incoming_cookie.is_some() {
+        response
+            .response_mut()
+            .add_removal_cookie(&Cookie::build(FLASH_COOKIE_NAME, "").path("/").finish())?;
+    }
+    Ok(response)
+}
+
+fn signed_cookie(
+    key: &Key,
+    messages: &[FlashMessage],
+) -> Result<Cookie<'static>, serde_json::Error> {
+    let value = base64::encode(serde_json::to_vec(messages)?);
+    let cookie = Cookie::build(FLASH_COOKIE_NAME, value)
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .finish();
+    let mut jar = CookieJar::new();
+    jar.signed_mut(key).add(cookie);
+    Ok(jar
+        .get(FLASH_COOKIE_NAME)
+        .cloned()
+        .expect("The signed flash cookie was just added to the jar"))
+}
+
+fn verify_cookie(key: &Key, cookie: Cookie<'static>) -> Option<Vec<FlashMessage>> {
+    let mut jar = CookieJar::new();
+    jar.add_original(cookie);
+    let cookie = jar.signed(key).get(FLASH_COOKIE_NAME)?;
+    let decoded = base64::decode(cookie.value()).ok()?;
+    serde_json::from_slice(&decoded).ok()
+}
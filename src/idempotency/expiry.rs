@@ -0,0 +1,36 @@
+use crate::configuration::IdempotencySettings;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use std::time::Duration;
+
+/// Periodically delete idempotency keys older than their TTL.
+pub async fn run_expiry_until_stopped(pool: PgPool, settings: IdempotencySettings) {
+    let key_ttl = settings.key_ttl();
+    loop {
+        tokio::time::sleep(settings.expiry_interval()).await;
+        if let Err(e) = delete_expired_keys(&pool, key_ttl).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to delete expired idempotency keys"
+            );
+        }
+    }
+}
+
+#[tracing::instrument(name = "Delete expired idempotency keys", skip(pool))]
+async fn delete_expired_keys(pool: &PgPool, key_ttl: Duration) -> Result<(), anyhow::Error> {
+    let expired_before = Utc::now()
+        - chrono::Duration::from_std(key_ttl).context("The idempotency TTL is too large")?;
+    let deleted = sqlx::query!(
+        r#"DELETE FROM idempotency WHERE created_at < $1"#,
+        expired_before
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete expired idempotency keys")?
+    .rows_affected();
+    tracing::info!(deleted, "Deleted expired idempotency keys");
+    Ok(())
+}
@@ -0,0 +1,7 @@
+mod expiry;
+mod key;
+mod persistence;
+
+pub use expiry::run_expiry_until_stopped;
+pub use key::IdempotencyKey;
+pub use persistence::{save_response, try_processing, NextAction};
@@ -0,0 +1,177 @@
+use super::IdempotencyKey;
+use actix_web::body::to_bytes;
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use anyhow::Context;
+use chrono::Utc;
+use sha3::Digest;
+use sqlx::postgres::{PgHasArrayType, PgTypeInfo};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+impl PgHasArrayType for HeaderPairRecord {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_header_pair")
+    }
+}
+
+/// What a request should do about its idempotency key.
+#[allow(clippy::large_enum_variant)]
+pub enum NextAction {
+    /// This is the first request with the key: go ahead, then hand the transaction to
+    /// [`save_response`].
+    StartProcessing(Transaction<'static, Postgres>),
+    /// The key was used before: replay what was answered then.
+    ReturnSavedResponse(HttpResponse),
+    /// The key was used before, for a request with another body: it was not a retry.
+    RejectReusedKey,
+}
+
+/// Claim an idempotency key for the current request.
+///
+/// The claim is made within a transaction that stays open until [`save_response`]: a concurrent
+/// request with the same key waits on it, then replays the response it saved. If processing
+/// fails, dropping the transaction releases the key for a retry. Keys older than `ttl` are
+/// considered free again.
+///
+/// A hash of `request_body` is stored with the key, so that a request reusing it with another
+/// body is told apart from a retry.
+#[tracing::instrument(name = "Claim an idempotency key", skip(pool, request_body, ttl))]
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    request_body: &[u8],
+    ttl: std::time::Duration,
+) -> Result<NextAction, anyhow::Error> {
+    let request_hash = format!("{:x}", sha3::Sha3_256::digest(request_body));
+    let expired_before =
+        Utc::now() - chrono::Duration::from_std(ttl).context("The idempotency TTL is too large")?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"
+        DELETE FROM idempotency
+        WHERE user_id = $1 AND idempotency_key = $2 AND created_at < $3
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        expired_before
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to remove an expired idempotency key")?;
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO idempotency (user_id, idempotency_key, request_hash, created_at)
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        request_hash
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to claim an idempotency key")?
+    .rows_affected();
+    if n_inserted_rows > 0 {
+        return Ok(NextAction::StartProcessing(transaction));
+    }
+    let (saved_hash, saved_response) = get_saved_response(pool, idempotency_key, user_id)
+        .await?
+        .context("We expected a saved response, we didn't find it")?;
+    if saved_hash.is_some_and(|saved_hash| saved_hash != request_hash) {
+        return Ok(NextAction::RejectReusedKey);
+    }
+    Ok(NextAction::ReturnSavedResponse(saved_response))
+}
+
+/// The response saved for the key, along with the hash of the request it answered.
+async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<(Option<String>, HttpResponse)>, anyhow::Error> {
+    let saved_response = sqlx::query!(
+        r#"
+        SELECT
+            request_hash,
+            response_status_code as "response_status_code!",
+            response_headers as "response_headers!: Vec<HeaderPairRecord>",
+            response_body as "response_body!"
+        FROM idempotency
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a saved response")?;
+    let r = match saved_response {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+    let status_code = StatusCode::from_u16(r.response_status_code.try_into()?)?;
+    let mut response = HttpResponse::build(status_code);
+    for HeaderPairRecord { name, value } in r.response_headers {
+        response.append_header((name, value));
+    }
+    Ok(Some((r.request_hash, response.body(r.response_body))))
+}
+
+/// Store the response to the request that claimed the key, and release the claim.
+#[tracing::instrument(name = "Save an idempotent response", skip(transaction, http_response))]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    http_response: HttpResponse,
+) -> Result<HttpResponse, anyhow::Error> {
+    let (response_head, body) = http_response.into_parts();
+    // `MessageBody::Error` is not `Send` + `Sync`, so it doesn't play nicely with `anyhow`.
+    let body = to_bytes(body).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+    let status_code = response_head.status().as_u16() as i16;
+    let headers: Vec<HeaderPairRecord> = response_head
+        .headers()
+        .iter()
+        .map(|(name, value)| HeaderPairRecord {
+            name: name.as_str().to_owned(),
+            value: value.as_bytes().to_owned(),
+        })
+        .collect();
+    sqlx::query_unchecked!(
+        r#"
+        UPDATE idempotency
+        SET
+            response_status_code = $3,
+            response_headers = $4,
+            response_body = $5
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        status_code,
+        headers,
+        body.as_ref()
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to save the response")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the saved response")?;
+
+    Ok(response_head.set_body(body).map_into_boxed_body())
+}
@@ -0,0 +1,641 @@
+//! src/issue_delivery_worker.rs
+
+use crate::configuration::IssueDeliverySettings;
+use crate::domain::{NewsletterTemplate, Recipient, SubscriberEmail};
+use crate::email_client::{EmailClient, EmailError};
+use crate::routes::generate_subscription_token;
+use anyhow::Context;
+use chrono::Utc;
+use futures::stream::{self, StreamExt};
+use rand::Rng;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+/// How long a worker backs off after failing to reach the database.
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+pub enum ExecutionOutcome {
+    BatchCompleted,
+    EmptyQueue,
+}
+
+/// Where the copy of an issue sent to one recipient is at, as kept in the delivery log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// Waiting for a worker, possibly to be retried.
+    Queued,
+    Sent,
+    /// Retries were exhausted, or the recipient's address is unusable.
+    Failed,
+    /// The email API rejected the email for good.
+    Bounced,
+    /// Not sent, as the recipient unsubscribed since the issue was published.
+    Skipped,
+}
+
+impl DeliveryStatus {
+    pub const ALL: [DeliveryStatus; 5] = [
+        DeliveryStatus::Queued,
+        DeliveryStatus::Sent,
+        DeliveryStatus::Failed,
+        DeliveryStatus::Bounced,
+        DeliveryStatus::Skipped,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Queued => "queued",
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Failed => "failed",
+            DeliveryStatus::Bounced => "bounced",
+            DeliveryStatus::Skipped => "skipped",
+        }
+    }
+}
+
+/// How deliveries that failed for a transient reason are retried.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: i16,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with jitter, so that deliveries failing together don't all retry at
+    /// once: somewhere between half and all of `base_delay * 2^n_retries`, capped at `max_delay`.
+    pub fn delay(&self, n_retries: i16) -> Duration {
+        let exponent = n_retries.clamp(0, 30) as u32;
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.max_delay);
+        let millis = delay.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(millis / 2..=millis))
+    }
+}
+
+/// Queue one delivery per confirmed subscriber, for the background workers to pick up.
+#[tracing::instrument(skip_all)]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        SELECT $1, email
+        FROM subscriptions
+        WHERE status = 'confirmed'
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to enqueue the delivery tasks")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (
+            newsletter_issue_id, subscriber_email, status, queued_at, updated_at
+        )
+        SELECT newsletter_issue_id, subscriber_email, 'queued', now(), now()
+        FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to log the queued deliveries")?;
+    Ok(())
+}
+
+/// Drain the delivery queue with `settings.workers` concurrent workers, forever.
+pub async fn run_workers_until_stopped(
+    pool: PgPool,
+    email_client: EmailClient,
+    settings: IssueDeliverySettings,
+    base_url: String,
+) {
+    let mut workers = JoinSet::new();
+    for _ in 0..settings.workers.max(1) {
+        workers.spawn(worker_loop(
+            pool.clone(),
+            email_client.clone(),
+            settings.clone(),
+            base_url.clone(),
+        ));
+    }
+    while workers.join_next().await.is_some() {}
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    settings: IssueDeliverySettings,
+    base_url: String,
+) {
+    let mut cursor = None;
+    loop {
+        match try_execute_batch(&pool, &email_client, &settings, &base_url, &mut cursor).await {
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(settings.poll_interval()).await,
+            Ok(ExecutionOutcome::BatchCompleted) => {}
+            Err(_) => tokio::time::sleep(ERROR_BACKOFF).await,
+        }
+    }
+}
+
+/// Where a worker is at in the queue: the key of the last task of its previous batch.
+pub type QueueCursor = Option<(Uuid, String)>;
+
+/// Deliver a batch of the queued tasks that are due, sending up to `settings.send_concurrency`
+/// emails at once.
+///
+/// Tasks are claimed with `FOR UPDATE SKIP LOCKED`, so that concurrent workers never pick the
+/// same one, and pushed back by the claim TTL so that other workers leave them alone meanwhile.
+/// Each worker walks the queue in key order from its `cursor`, rather than rescanning it from
+/// the start, and wraps around once it reaches the end. Transient failures put the task back in
+/// the queue for later, until the retry policy gives up. Every attempt is recorded in the
+/// delivery log as soon as its email has been sent, so that a failure later in the batch can't
+/// send it again.
+///
+/// Each email is personalized for its recipient, with links to the app starting with `base_url`.
+#[tracing::instrument(skip_all, err)]
+pub async fn try_execute_batch(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    settings: &IssueDeliverySettings,
+    base_url: &str,
+    cursor: &mut QueueCursor,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let tasks = dequeue_tasks(pool, cursor, settings.batch_size, settings.claim_ttl()).await?;
+    let last_task = match tasks.last() {
+        Some(task) => task,
+        // Tasks behind the cursor may be due by now, so look again from the start.
+        None if cursor.take().is_some() => return Ok(ExecutionOutcome::BatchCompleted),
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    *cursor = Some((
+        last_task.newsletter_issue_id,
+        last_task.subscriber_email.clone(),
+    ));
+    let issues = get_issues(pool, &tasks).await?;
+    let subscribers = get_subscribers(pool, &tasks, base_url).await?;
+    let retry_policy = settings.retry_policy();
+    let started_at = Instant::now();
+    let outcomes: Vec<Result<DeliveryStatus, anyhow::Error>> = stream::iter(tasks)
+        .map(|task| {
+            let issue = issues.get(&task.newsletter_issue_id);
+            let subscriber = subscribers.get(&task.subscriber_email);
+            async move {
+                let delivery = deliver(email_client, issue, subscriber, &task, &retry_policy).await;
+                record_delivery(pool, &task, &delivery).await
+            }
+        })
+        .buffer_unordered(settings.send_concurrency.max(1))
+        .collect()
+        .await;
+    let elapsed = started_at.elapsed();
+    let batch_size = outcomes.len();
+    let mut tally = [0usize; DeliveryStatus::ALL.len()];
+    let mut first_error = None;
+    for outcome in outcomes {
+        match outcome {
+            Ok(status) => tally[status as usize] += 1,
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    let [retrying, sent, failed, bounced, skipped] = tally;
+    tracing::info!(
+        batch_size,
+        sent,
+        retrying,
+        failed,
+        bounced,
+        skipped,
+        elapsed_millis = elapsed.as_millis() as u64,
+        emails_per_second = batch_size as f64 / elapsed.as_secs_f64().max(0.001),
+        "Delivered a batch of queued emails",
+    );
+    // The tasks left unrecorded are picked up again once their claim expires.
+    if let Some(e) = first_error {
+        return Err(e);
+    }
+    Ok(ExecutionOutcome::BatchCompleted)
+}
+
+/// What came of sending an issue to one recipient.
+enum Delivery {
+    Sent,
+    Retry {
+        delay: Duration,
+        error: String,
+    },
+    GaveUp {
+        status: DeliveryStatus,
+        error: String,
+    },
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id=%task.newsletter_issue_id,
+        subscriber_email=%task.subscriber_email,
+        n_retries=task.n_retries
+    )
+)]
+async fn deliver(
+    email_client: &EmailClient,
+    issue: Option<&NewsletterIssue>,
+    subscriber: Option<&Subscriber>,
+    task: &Task,
+    retry_policy: &RetryPolicy,
+) -> Delivery {
+    let email = match SubscriberEmail::parse(&task.subscriber_email) {
+        Ok(email) => email,
+        Err(e) => {
+            tracing::error!(
+                error.message = %e,
+                "Skipping a confirmed subscriber. Their stored contact details are invalid",
+            );
+            return Delivery::GaveUp {
+                status: DeliveryStatus::Failed,
+                error: e,
+            };
+        }
+    };
+    let Some(issue) = issue else {
+        tracing::error!("Dropping the delivery of an issue that no longer exists");
+        return Delivery::GaveUp {
+            status: DeliveryStatus::Skipped,
+            error: "The issue no longer exists.".into(),
+        };
+    };
+    let Some(subscriber) = subscriber else {
+        tracing::warn!("Skipping a recipient who is no longer a confirmed subscriber");
+        return Delivery::GaveUp {
+            status: DeliveryStatus::Skipped,
+            error: "The subscriber unsubscribed or no longer exists.".into(),
+        };
+    };
+    let recipient = Recipient {
+        name: &subscriber.name,
+        email: email.as_ref(),
+        unsubscribe_url: &subscriber.unsubscribe_url,
+    };
+    let (subject, html_content, text_content) = match issue.personalize(&recipient) {
+        Ok(personalized) => personalized,
+        Err(e) => {
+            tracing::error!(error.message = %e, "Failed to fill in the template of an issue");
+            return Delivery::GaveUp {
+                status: DeliveryStatus::Failed,
+                error: e,
+            };
+        }
+    };
+    let outcome = email_client
+        .send_email(&email, &subject, &html_content, &text_content)
+        .await;
+    match outcome {
+        Ok(()) => Delivery::Sent,
+        Err(EmailError::Transient(e)) if task.n_retries < retry_policy.max_retries => {
+            let delay = retry_policy.delay(task.n_retries);
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                retry_in_millis = delay.as_millis() as u64,
+                "Failed to deliver issue to a confirmed subscriber. Retrying later.",
+            );
+            Delivery::Retry {
+                delay,
+                error: describe(&EmailError::Transient(e)),
+            }
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber. Giving up.",
+            );
+            // The email API refusing the email itself is as good as a bounce.
+            let status = match e {
+                EmailError::Permanent(_) => DeliveryStatus::Bounced,
+                EmailError::Transient(_) => DeliveryStatus::Failed,
+            };
+            Delivery::GaveUp {
+                status,
+                error: describe(&e),
+            }
+        }
+    }
+}
+
+fn describe(e: &EmailError) -> String {
+    match std::error::Error::source(e) {
+        Some(source) => format!("{} {}", e, source),
+        None => e.to_string(),
+    }
+}
+
+/// Log the delivery, then either put its task back in the queue or remove it, in a
+/// transaction of its own.
+async fn record_delivery(
+    pool: &PgPool,
+    task: &Task,
+    delivery: &Delivery,
+) -> Result<DeliveryStatus, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let status = match delivery {
+        Delivery::Sent => {
+            log_delivery(&mut transaction, task, DeliveryStatus::Sent, None).await?;
+            delete_task(&mut transaction, task).await?;
+            DeliveryStatus::Sent
+        }
+        Delivery::Retry { delay, error } => {
+            let error = Some(error.clone());
+            log_delivery(&mut transaction, task, DeliveryStatus::Queued, error).await?;
+            schedule_retry(&mut transaction, task, *delay).await?;
+            DeliveryStatus::Queued
+        }
+        Delivery::GaveUp { status, error } => {
+            log_delivery(&mut transaction, task, *status, Some(error.clone())).await?;
+            delete_task(&mut transaction, task).await?;
+            *status
+        }
+    };
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the outcome of a delivery")?;
+    Ok(status)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+}
+
+/// Claim up to `batch_size` due tasks past `cursor` for `claim_ttl`, in key order.
+async fn dequeue_tasks(
+    pool: &PgPool,
+    cursor: &QueueCursor,
+    batch_size: i64,
+    claim_ttl: Duration,
+) -> Result<Vec<Task>, anyhow::Error> {
+    let claimed_until = Utc::now() + chrono::Duration::from_std(claim_ttl)?;
+    let mut tasks = sqlx::query_as!(
+        Task,
+        r#"
+        WITH due AS (
+            SELECT newsletter_issue_id, subscriber_email
+            FROM issue_delivery_queue
+            WHERE execute_after <= now()
+                AND ($1::uuid IS NULL OR (newsletter_issue_id, subscriber_email) > ($1::uuid, $2::text))
+            ORDER BY newsletter_issue_id, subscriber_email
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT $3
+        )
+        UPDATE issue_delivery_queue AS queue
+        SET execute_after = $4
+        FROM due
+        WHERE queue.newsletter_issue_id = due.newsletter_issue_id
+            AND queue.subscriber_email = due.subscriber_email
+        RETURNING queue.newsletter_issue_id, queue.subscriber_email, queue.n_retries
+        "#,
+        cursor.as_ref().map(|(issue_id, _)| *issue_id),
+        cursor.as_ref().map(|(_, email)| email.as_str()),
+        batch_size,
+        claimed_until
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to dequeue delivery tasks")?;
+    tasks.sort_by(|a, b| {
+        (a.newsletter_issue_id, &a.subscriber_email)
+            .cmp(&(b.newsletter_issue_id, &b.subscriber_email))
+    });
+    Ok(tasks)
+}
+
+async fn schedule_retry(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let execute_after = Utc::now() + chrono::Duration::from_std(delay)?;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_retries = n_retries + 1, execute_after = $3
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        execute_after
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to schedule the retry of a delivery task")?;
+    Ok(())
+}
+
+/// Record the outcome of a delivery attempt in the delivery log.
+async fn log_delivery(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    status: DeliveryStatus,
+    error: Option<String>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_deliveries
+        SET status = $3, n_attempts = n_attempts + 1, error = $4, updated_at = now()
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        status.as_str(),
+        error
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to log a delivery attempt")?;
+    Ok(())
+}
+
+async fn delete_task(transaction: &mut PgTransaction, task: &Task) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .execute(transaction)
+    .await
+    .context("Failed to delete a completed delivery task")?;
+    Ok(())
+}
+
+struct NewsletterIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+impl NewsletterIssue {
+    /// The subject and bodies for one recipient. Templates are validated before an issue is
+    /// published, so this only fails for issues published before they were templates.
+    fn personalize(&self, recipient: &Recipient) -> Result<(String, String, String), String> {
+        Ok((
+            NewsletterTemplate::parse(&self.title)?.render(recipient),
+            NewsletterTemplate::parse(&self.html_content)?.render_html(recipient),
+            NewsletterTemplate::parse(&self.text_content)?.render(recipient),
+        ))
+    }
+}
+
+/// The issues the tasks of a batch deliver, fetched once rather than once per recipient.
+async fn get_issues(
+    pool: &PgPool,
+    tasks: &[Task],
+) -> Result<HashMap<Uuid, NewsletterIssue>, anyhow::Error> {
+    let mut issue_ids: Vec<Uuid> = tasks.iter().map(|task| task.newsletter_issue_id).collect();
+    issue_ids.dedup();
+    let issues = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT newsletter_issue_id, title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = ANY($1)
+        "#,
+        &issue_ids
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve newsletter issues")?;
+    Ok(issues
+        .into_iter()
+        .map(|issue| (issue.newsletter_issue_id, issue))
+        .collect())
+}
+
+/// What fills in the template variables of an issue for one recipient.
+struct Subscriber {
+    name: String,
+    unsubscribe_url: String,
+}
+
+/// The subscribers the tasks of a batch deliver to, keyed by email.
+///
+/// Only confirmed subscribers are returned: those who unsubscribed since the issue was
+/// published don't get it. The unsubscribe link carries a token of the subscriber's own, which
+/// they get here the first time.
+async fn get_subscribers(
+    pool: &PgPool,
+    tasks: &[Task],
+    base_url: &str,
+) -> Result<HashMap<String, Subscriber>, anyhow::Error> {
+    let emails: Vec<String> = tasks
+        .iter()
+        .map(|task| task.subscriber_email.clone())
+        .collect();
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, email, name, unsubscribe_token
+        FROM subscriptions
+        WHERE email = ANY($1) AND status = 'confirmed'
+        "#,
+        &emails
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the subscribers of a batch")?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let mut subscribers = HashMap::with_capacity(rows.len());
+    for row in rows {
+        let unsubscribe_token = match row.unsubscribe_token {
+            Some(token) => token,
+            // Another worker may be handing out a token to the same subscriber.
+            None => {
+                sqlx::query!(
+                    r#"
+                UPDATE subscriptions
+                SET unsubscribe_token = COALESCE(unsubscribe_token, $2)
+                WHERE id = $1
+                RETURNING unsubscribe_token AS "unsubscribe_token!"
+                "#,
+                    row.id,
+                    generate_subscription_token()
+                )
+                .fetch_one(&mut transaction)
+                .await
+                .context("Failed to store the unsubscribe token of a subscriber")?
+                .unsubscribe_token
+            }
+        };
+        let unsubscribe_url = format!(
+            "{}/subscriptions/unsubscribe?token={}",
+            base_url, unsubscribe_token
+        );
+        subscribers.insert(
+            row.email,
+            Subscriber {
+                name: row.name,
+                unsubscribe_url,
+            },
+        );
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the unsubscribe tokens of a batch")?;
+    Ok(subscribers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use std::time::Duration;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn retry_delays_grow_exponentially_with_jitter() {
+        for n_retries in 0..5 {
+            let full_delay = Duration::from_secs(2u64.pow(n_retries as u32));
+            let delay = policy().delay(n_retries);
+            assert!(delay >= full_delay / 2 && delay <= full_delay);
+        }
+    }
+
+    #[test]
+    fn retry_delays_are_capped() {
+        let delay = policy().delay(i16::MAX);
+        assert!(delay >= Duration::from_secs(30) && delay <= Duration::from_secs(60));
+    }
+}
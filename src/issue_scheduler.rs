@@ -0,0 +1,71 @@
+//! src/issue_scheduler.rs
+
+use crate::configuration::IssueSchedulingSettings;
+use crate::issue_delivery_worker::enqueue_delivery_tasks;
+use anyhow::Context;
+use sqlx::PgPool;
+
+/// Key of the advisory lock held while dispatching, shared by every kobo instance.
+pub const DISPATCH_LOCK_KEY: i64 = 0x6b6f_626f_0001;
+
+/// Dispatch scheduled issues as they fall due, forever.
+pub async fn run_scheduler_until_stopped(pool: PgPool, settings: IssueSchedulingSettings) {
+    loop {
+        if let Err(e) = dispatch_due_issues(&pool).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to dispatch scheduled newsletter issues"
+            );
+        }
+        tokio::time::sleep(settings.poll_interval()).await;
+    }
+}
+
+/// Publish the scheduled issues whose time has come, queueing their deliveries.
+///
+/// Only one instance dispatches at a time: the others skip their turn while the advisory lock is
+/// taken. Issues are published and their deliveries queued in the same transaction, so editing
+/// or cancelling an issue fails once its dispatch has started.
+#[tracing::instrument(name = "Dispatch scheduled newsletter issues", skip(pool))]
+pub async fn dispatch_due_issues(pool: &PgPool) -> Result<usize, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let locked = sqlx::query!(
+        r#"SELECT pg_try_advisory_xact_lock($1) AS "locked!""#,
+        DISPATCH_LOCK_KEY
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to take the dispatch lock")?
+    .locked;
+    if !locked {
+        tracing::debug!("Another instance is dispatching scheduled issues");
+        return Ok(0);
+    }
+    let due_issues = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'published', published_at = now()
+        WHERE status = 'scheduled' AND publish_at <= now()
+        RETURNING newsletter_issue_id
+        "#
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to publish the scheduled issues that are due")?;
+    for issue in &due_issues {
+        enqueue_delivery_tasks(&mut transaction, issue.newsletter_issue_id).await?;
+        tracing::info!(
+            newsletter_issue_id = %issue.newsletter_issue_id,
+            "Dispatched a scheduled newsletter issue"
+        );
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the dispatch of scheduled issues")?;
+    Ok(due_issues.len())
+}
@@ -1,6 +1,16 @@
+pub mod authentication;
+pub mod cli;
 pub mod configuration;
+pub mod csrf;
 pub mod domain;
 pub mod email_client;
+pub mod flash_messages;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
 pub mod routes;
+pub mod session_state;
+pub mod session_store;
 pub mod startup;
 pub mod telemetry;
+pub mod utils;
@@ -1,18 +1,30 @@
+use clap::Parser;
+use kobo::cli::{run_admin_command, Cli, Command};
 use kobo::configuration::get_configuration;
 
-use kobo::startup::Application;
-use kobo::{telemetry};
+use kobo::startup::{get_connection_pool, Application};
+use kobo::telemetry;
 
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
-    telemetry::init_subscriber(telemetry::get_subscriber("kobo".into(), "info".into()));
-
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
     let configuration = get_configuration().expect("Failed to read configuration");
-    let application = Application::build(&configuration)
-        .await
-        .expect("unable to build app");
 
-    println!("server listening on port: {:?}", application.port());
-    let _ = application.run_until_stopped().await;
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => {
+            telemetry::init_subscriber(telemetry::get_subscriber("kobo".into(), "info".into()));
+            let application = Application::build(&configuration)
+                .await
+                .expect("unable to build app");
+
+            println!("server listening on port: {:?}", application.port());
+            let _ = application.run_until_stopped().await;
+        }
+        Command::Admin(command) => {
+            let pool = get_connection_pool(&configuration.database);
+            let policy = configuration.password_hashing.policy()?;
+            print!("{}", run_admin_command(command, &pool, &policy).await?);
+        }
+    }
     Ok(())
 }
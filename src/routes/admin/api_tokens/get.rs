@@ -0,0 +1,52 @@
+use crate::authentication::{list_api_tokens, Scope, UserId};
+use crate::csrf::CsrfToken;
+use crate::flash_messages::IncomingFlashMessages;
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+pub async fn api_tokens(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let tokens = list_api_tokens(&pool, **user_id).await.map_err(e500)?;
+    let tokens_html: String = tokens
+        .iter()
+        .map(|token| {
+            format!(
+                r#"<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td><form action="/admin/api-tokens/{}/revoke" method="post">{}<button type="submit">Revoke</button></form></td></tr>
+"#,
+                htmlescape::encode_minimal(&token.name),
+                htmlescape::encode_minimal(&token.scopes.join(", ")),
+                token.created_at.format("%Y-%m-%d %H:%M UTC"),
+                token.last_used_at.map_or_else(
+                    || "Never".to_string(),
+                    |t| t.format("%Y-%m-%d %H:%M UTC").to_string()
+                ),
+                token.token_id,
+                csrf_token.to_html()
+            )
+        })
+        .collect();
+    let scopes_html: String = Scope::ALL
+        .iter()
+        .map(|scope| {
+            format!(
+                "<label><input type=\"checkbox\" name=\"{0}\" value=\"on\"> {0}</label><br>\n",
+                scope.as_str()
+            )
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("api_tokens.html"),
+            flash_messages = flash_messages.to_html(),
+            csrf_field = csrf_token.to_html(),
+            tokens = tokens_html,
+            scopes = scopes_html
+        )))
+}
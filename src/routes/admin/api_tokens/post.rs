@@ -0,0 +1,63 @@
+use crate::authentication::{create_api_token, revoke_api_token, Scope, UserId};
+use crate::flash_messages::FlashMessage;
+use crate::utils::{e500, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const MAX_TOKEN_NAME_LENGTH: usize = 100;
+
+/// The form holds the token name plus one checkbox per scope, named after the scope.
+pub async fn create_api_token_form(
+    form: web::Form<HashMap<String, String>>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = form.get("name").map(|n| n.trim()).unwrap_or_default();
+    if name.is_empty() || name.chars().count() > MAX_TOKEN_NAME_LENGTH {
+        FlashMessage::error(format!(
+            "The token name must be between 1 and {} characters long.",
+            MAX_TOKEN_NAME_LENGTH
+        ))
+        .send();
+        return Ok(see_other("/admin/api-tokens"));
+    }
+    let scopes: Vec<Scope> = Scope::ALL
+        .into_iter()
+        .filter(|scope| form.contains_key(scope.as_str()))
+        .collect();
+    if scopes.is_empty() {
+        FlashMessage::error("Select at least one scope for the token.").send();
+        return Ok(see_other("/admin/api-tokens"));
+    }
+
+    let token = create_api_token(&pool, **user_id, name, &scopes)
+        .await
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("api_token_created.html"),
+            name = htmlescape::encode_minimal(name),
+            token = token.expose_secret()
+        )))
+}
+
+pub async fn revoke_api_token_form(
+    token_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if revoke_api_token(&pool, **user_id, token_id.into_inner())
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::info("The API token has been revoked.").send();
+    } else {
+        FlashMessage::error("The API token was not found.").send();
+    }
+    Ok(see_other("/admin/api-tokens"))
+}
@@ -0,0 +1,71 @@
+use crate::authentication::{get_role, Permission, UserId};
+use crate::csrf::CsrfToken;
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub async fn admin_dashboard(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    let role = get_role(&pool, *user_id).await.map_err(e500)?;
+    let subscriber_counts = get_subscriber_counts(&pool).await.map_err(e500)?;
+    let subscriber_counts_html: String = subscriber_counts
+        .iter()
+        .map(|(status, count)| {
+            format!(
+                "<li>{}: {}</li>\n",
+                htmlescape::encode_minimal(status),
+                count
+            )
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("dashboard.html"),
+            username = htmlescape::encode_minimal(&username),
+            role = role,
+            csrf_field = csrf_token.to_html(),
+            manage_editors = if role.can(Permission::ManageEditors) {
+                "<li><a href=\"/admin/editors\">Manage editors</a></li>"
+            } else {
+                ""
+            },
+            subscriber_counts = subscriber_counts_html
+        )))
+}
+
+#[tracing::instrument(name = "Get username", skip(pool))]
+pub async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT username FROM editors WHERE user_id = $1"#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform a query to retrieve a username.")?;
+    Ok(row.username)
+}
+
+#[tracing::instrument(name = "Count subscribers by status", skip(pool))]
+async fn get_subscriber_counts(pool: &PgPool) -> Result<Vec<(String, i64)>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT status, COUNT(*) AS "count!"
+        FROM subscriptions
+        GROUP BY status
+        ORDER BY status
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to count subscribers by status.")?;
+    Ok(rows.into_iter().map(|r| (r.status, r.count)).collect())
+}
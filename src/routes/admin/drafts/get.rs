@@ -0,0 +1,151 @@
+use crate::csrf::CsrfToken;
+use crate::flash_messages::IncomingFlashMessages;
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub async fn drafts(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let drafts = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title, editors.username AS "author?", updated_at
+        FROM newsletter_issues
+        LEFT JOIN editors ON editors.user_id = newsletter_issues.created_by
+        WHERE status = 'draft'
+        ORDER BY updated_at DESC
+        "#
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to retrieve drafts")
+    .map_err(e500)?;
+    let drafts_html: String = drafts
+        .iter()
+        .map(|draft| {
+            format!(
+                "<tr><td><a href=\"/admin/drafts/{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+                draft.newsletter_issue_id,
+                htmlescape::encode_minimal(&draft.title),
+                htmlescape::encode_minimal(draft.author.as_deref().unwrap_or("")),
+                draft.updated_at.format("%Y-%m-%d %H:%M UTC")
+            )
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("drafts.html"),
+            flash_messages = flash_messages.to_html(),
+            csrf_field = csrf_token.to_html(),
+            drafts = drafts_html
+        )))
+}
+
+pub(crate) struct Draft {
+    pub(crate) title: String,
+    /// `None` for drafts saved before issues were written in Markdown.
+    pub(crate) markdown_content: Option<String>,
+    pub(crate) text_content: String,
+    pub(crate) html_content: String,
+}
+
+/// `None` if there is no such issue, or if it was published already.
+pub(crate) async fn get_draft(
+    pool: &PgPool,
+    draft_id: Uuid,
+) -> Result<Option<Draft>, anyhow::Error> {
+    let draft = sqlx::query_as!(
+        Draft,
+        r#"
+        SELECT title, markdown_content, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        draft_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the draft")?;
+    Ok(draft)
+}
+
+pub async fn draft(
+    draft_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let draft = match get_draft(&pool, draft_id).await.map_err(e500)? {
+        Some(draft) => draft,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    // Older drafts are edited from their plain text, which is valid Markdown for the most part.
+    let markdown_content = draft
+        .markdown_content
+        .as_deref()
+        .unwrap_or(&draft.text_content);
+    // Test copies only go to editors, never to an arbitrary address.
+    let editors = sqlx::query!(
+        r#"
+        SELECT user_id, username, email AS "email!"
+        FROM editors
+        WHERE email IS NOT NULL AND disabled_at IS NULL
+        ORDER BY username
+        "#
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to retrieve editors")
+    .map_err(e500)?;
+    let editors_html: String = editors
+        .iter()
+        .map(|editor| {
+            format!(
+                "<label><input type=\"checkbox\" name=\"{}\"> {} &lt;{}&gt;</label><br>\n",
+                editor.user_id,
+                htmlescape::encode_minimal(&editor.username),
+                htmlescape::encode_minimal(&editor.email)
+            )
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("draft.html"),
+            flash_messages = flash_messages.to_html(),
+            csrf_field = csrf_token.to_html(),
+            draft_id = draft_id,
+            title = htmlescape::encode_attribute(&draft.title),
+            markdown_content = htmlescape::encode_minimal(markdown_content),
+            editors = editors_html
+        )))
+}
+
+/// Show a draft the way subscribers will get it. The HTML is rendered in a sandboxed frame, so
+/// that scripts in the content don't run with the admin's session.
+pub async fn draft_preview(
+    draft_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let draft = match get_draft(&pool, draft_id).await.map_err(e500)? {
+        Some(draft) => draft,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("draft_preview.html"),
+            draft_id = draft_id,
+            title = htmlescape::encode_minimal(&draft.title),
+            html_content = htmlescape::encode_attribute(&draft.html_content),
+            text_content = htmlescape::encode_minimal(&draft.text_content)
+        )))
+}
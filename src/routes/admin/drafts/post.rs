@@ -0,0 +1,213 @@
+use super::get_draft;
+use crate::authentication::{require_permission, Permission, UserId};
+use crate::domain::{NewsletterContent, NewsletterTemplate, Recipient, SubscriberEmail};
+use crate::email_client::EmailClient;
+use crate::flash_messages::FlashMessage;
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// The largest draft form accepted, far above the 16 KiB forms are limited to by default.
+///
+/// URL-encoding can triple the size of the Markdown: this leaves room for it, so that content
+/// over `NewsletterContent::MAX_LENGTH` gets a validation error rather than a bare 413.
+pub const DRAFT_FORM_LIMIT: usize = 4 * NewsletterContent::MAX_LENGTH;
+
+#[derive(Deserialize)]
+pub struct DraftForm {
+    title: String,
+    markdown_content: String,
+}
+
+impl DraftForm {
+    /// Render the Markdown of a valid draft.
+    fn validate(&self) -> Result<NewsletterContent, String> {
+        if self.title.trim().is_empty() {
+            return Err("The draft needs a title.".into());
+        }
+        NewsletterTemplate::parse(&self.title)?;
+        NewsletterContent::parse(&self.markdown_content)
+    }
+}
+
+pub async fn create_draft(
+    form: web::Form<DraftForm>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(&pool, **user_id, Permission::WriteDrafts).await?;
+    let content = match form.validate() {
+        Ok(content) => content,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/drafts"));
+        }
+    };
+    let draft_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, title, markdown_content, text_content, html_content, status,
+            created_by, updated_at
+        )
+        VALUES ($1, $2, $3, $4, $5, 'draft', $6, now())
+        "#,
+        draft_id,
+        form.title,
+        content.markdown(),
+        content.text(),
+        content.html(),
+        **user_id
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to store the draft")
+    .map_err(e500)?;
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&format!("/admin/drafts/{}", draft_id)))
+}
+
+pub async fn update_draft(
+    draft_id: web::Path<Uuid>,
+    form: web::Form<DraftForm>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(&pool, **user_id, Permission::WriteDrafts).await?;
+    let draft_id = draft_id.into_inner();
+    let draft_url = format!("/admin/drafts/{}", draft_id);
+    let content = match form.validate() {
+        Ok(content) => content,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&draft_url));
+        }
+    };
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = $2, markdown_content = $3, text_content = $4, html_content = $5,
+            updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        draft_id,
+        form.title,
+        content.markdown(),
+        content.text(),
+        content.html()
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to update the draft")
+    .map_err(e500)?
+    .rows_affected();
+    if updated == 0 {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&draft_url))
+}
+
+/// The form holds one checkbox per editor, named after their user id.
+///
+/// Test copies are personalized for the editors receiving them, but their unsubscribe link
+/// carries no subscription token.
+pub async fn send_test_email(
+    draft_id: web::Path<Uuid>,
+    form: web::Form<HashMap<String, String>>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(&pool, **user_id, Permission::WriteDrafts).await?;
+    let draft_id = draft_id.into_inner();
+    let draft_url = format!("/admin/drafts/{}", draft_id);
+    let draft = match get_draft(&pool, draft_id).await.map_err(e500)? {
+        Some(draft) => draft,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let editor_ids: Vec<Uuid> = form
+        .keys()
+        .filter_map(|key| Uuid::parse_str(key).ok())
+        .collect();
+    let recipients = get_editor_emails(&pool, &editor_ids).await.map_err(e500)?;
+    if recipients.is_empty() {
+        FlashMessage::error("Select at least one editor to send the test copy to.").send();
+        return Ok(see_other(&draft_url));
+    }
+    let templates = (
+        NewsletterTemplate::parse(&draft.title),
+        NewsletterTemplate::parse(&draft.html_content),
+        NewsletterTemplate::parse(&draft.text_content),
+    );
+    let (title, html_content, text_content) = match templates {
+        (Ok(title), Ok(html_content), Ok(text_content)) => (title, html_content, text_content),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&draft_url));
+        }
+    };
+    let unsubscribe_url = format!("{}/subscriptions/unsubscribe", base_url.0);
+    let mut n_sent = 0;
+    for (username, email) in &recipients {
+        let recipient = Recipient {
+            name: username,
+            email,
+            unsubscribe_url: &unsubscribe_url,
+        };
+        let subject = format!("[Test] {}", title.render(&recipient));
+        let outcome = match SubscriberEmail::parse(email) {
+            Ok(address) => email_client
+                .send_email(
+                    &address,
+                    &subject,
+                    &html_content.render_html(&recipient),
+                    &text_content.render(&recipient),
+                )
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        match outcome {
+            Ok(()) => n_sent += 1,
+            Err(e) => {
+                tracing::warn!(error.message = %e, "Failed to send a test copy of a draft");
+                FlashMessage::error(format!("Failed to send the test copy to {}.", email)).send();
+            }
+        }
+    }
+    if n_sent > 0 {
+        FlashMessage::info(format!("Sent a test copy to {} editor(s).", n_sent)).send();
+    }
+    Ok(see_other(&draft_url))
+}
+
+/// The username and email address of each editor.
+#[tracing::instrument(name = "Get editor emails", skip(pool))]
+async fn get_editor_emails(
+    pool: &PgPool,
+    editor_ids: &[Uuid],
+) -> Result<Vec<(String, String)>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT username, email AS "email!"
+        FROM editors
+        WHERE user_id = ANY($1) AND email IS NOT NULL AND disabled_at IS NULL
+        ORDER BY email
+        "#,
+        editor_ids
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the editors' email addresses")?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.username, row.email))
+        .collect())
+}
@@ -0,0 +1,53 @@
+use crate::authentication::{require_permission, Permission, Role, UserId};
+use crate::csrf::CsrfToken;
+use crate::flash_messages::IncomingFlashMessages;
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+pub async fn editors(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(&pool, **user_id, Permission::ManageEditors).await?;
+    let editors = sqlx::query!(r#"SELECT user_id, username, role FROM editors ORDER BY username"#)
+        .fetch_all(pool.as_ref())
+        .await
+        .context("Failed to retrieve editors")
+        .map_err(e500)?;
+    let editors_html: String = editors
+        .iter()
+        .map(|editor| {
+            let options: String = Role::ALL
+                .iter()
+                .map(|role| {
+                    let selected = if role.as_str() == editor.role {
+                        " selected"
+                    } else {
+                        ""
+                    };
+                    format!("<option value=\"{0}\"{1}>{0}</option>", role, selected)
+                })
+                .collect();
+            format!(
+                r#"<tr><td>{}</td><td><form action="/admin/editors/{}/role" method="post">{}<select name="role">{}</select><button type="submit">Change role</button></form></td></tr>
+"#,
+                htmlescape::encode_minimal(&editor.username),
+                editor.user_id,
+                csrf_token.to_html(),
+                options
+            )
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("editors.html"),
+            flash_messages = flash_messages.to_html(),
+            editors = editors_html
+        )))
+}
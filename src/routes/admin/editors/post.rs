@@ -0,0 +1,46 @@
+use crate::authentication::{require_permission, Permission, Role, UserId};
+use crate::flash_messages::FlashMessage;
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct ChangeRoleFormData {
+    role: String,
+}
+
+pub async fn change_editor_role(
+    form: web::Form<ChangeRoleFormData>,
+    editor_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(&pool, **user_id, Permission::ManageEditors).await?;
+    let editor_id = editor_id.into_inner();
+    // Otherwise the last owner could lock everyone out of editor management.
+    if editor_id == **user_id {
+        FlashMessage::error("You can't change your own role.").send();
+        return Ok(see_other("/admin/editors"));
+    }
+    let role = match Role::try_from(form.role.as_str()) {
+        Ok(role) => role,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/editors"));
+        }
+    };
+    sqlx::query!(
+        r#"UPDATE editors SET role = $1 WHERE user_id = $2"#,
+        role.as_str(),
+        editor_id
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to change an editor's role")
+    .map_err(e500)?;
+    FlashMessage::info("The editor's role has been changed.").send();
+    Ok(see_other("/admin/editors"))
+}
@@ -0,0 +1,10 @@
+use crate::flash_messages::FlashMessage;
+use crate::session_state::TypedSession;
+use crate::utils::see_other;
+use actix_web::HttpResponse;
+
+pub async fn log_out(session: TypedSession) -> HttpResponse {
+    session.log_out();
+    FlashMessage::info("You have successfully logged out.").send();
+    see_other("/login")
+}
@@ -0,0 +1,17 @@
+mod api_tokens;
+mod dashboard;
+mod drafts;
+mod editors;
+mod logout;
+mod newsletters;
+mod password;
+mod two_factor;
+
+pub use api_tokens::*;
+pub use dashboard::*;
+pub use drafts::*;
+pub use editors::*;
+pub use logout::*;
+pub use newsletters::*;
+pub use password::*;
+pub use two_factor::*;
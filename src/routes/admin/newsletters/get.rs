@@ -0,0 +1,208 @@
+use crate::authentication::{require_permission, Permission, UserId};
+use crate::csrf::CsrfToken;
+use crate::flash_messages::IncomingFlashMessages;
+use crate::issue_delivery_worker::DeliveryStatus;
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const FAILURES_PER_PAGE: i64 = 50;
+
+pub async fn newsletter_issues(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let issues = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title, published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE status = 'published'
+        ORDER BY published_at DESC
+        "#
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to retrieve newsletter issues")
+    .map_err(e500)?;
+    let issues_html: String = issues
+        .iter()
+        .map(|issue| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td><a href=\"/admin/newsletters/{}\">Report</a></td></tr>\n",
+                htmlescape::encode_minimal(&issue.title),
+                issue.published_at.format("%Y-%m-%d %H:%M UTC"),
+                issue.newsletter_issue_id
+            )
+        })
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("newsletters.html"),
+            issues = issues_html
+        )))
+}
+
+#[derive(Deserialize)]
+pub struct ReportQuery {
+    /// 1-based page of the failures list.
+    page: Option<i64>,
+}
+
+struct Failure {
+    subscriber_email: String,
+    status: String,
+    n_attempts: i16,
+    error: Option<String>,
+    updated_at: DateTime<Utc>,
+}
+
+/// How the delivery of an issue went: how many copies are in each state, and which failed.
+///
+/// Failures list subscriber emails, so the report is restricted to those managing subscribers.
+pub async fn delivery_report(
+    newsletter_issue_id: web::Path<Uuid>,
+    query: web::Query<ReportQuery>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(&pool, **user_id, Permission::ManageSubscribers).await?;
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue = sqlx::query!(
+        r#"
+        SELECT title, published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = 'published'
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to retrieve the newsletter issue")
+    .map_err(e500)?;
+    let issue = match issue {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let counts = get_status_counts(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+    let count_of = |status: DeliveryStatus| {
+        counts
+            .iter()
+            .find(|(s, _)| s == status.as_str())
+            .map(|(_, count)| *count)
+            .unwrap_or(0)
+    };
+    let status_counts_html: String = DeliveryStatus::ALL
+        .iter()
+        .map(|status| format!("<li>{}: {}</li>\n", status.as_str(), count_of(*status)))
+        .collect();
+    let resume_form = if count_of(DeliveryStatus::Failed) > 0 {
+        format!(
+            r#"<form action="/admin/newsletters/{}/resume" method="post">{}<button type="submit">Resume delivery to failed recipients</button></form>"#,
+            newsletter_issue_id,
+            csrf_token.to_html()
+        )
+    } else {
+        String::new()
+    };
+
+    let page = query.page.unwrap_or(1).max(1);
+    let mut failures = get_failures(&pool, newsletter_issue_id, page)
+        .await
+        .map_err(e500)?;
+    // One more failure than a page holds was fetched, to tell whether there is a next page.
+    let has_next_page = failures.len() as i64 > FAILURES_PER_PAGE;
+    failures.truncate(FAILURES_PER_PAGE as usize);
+    let failures_html: String = failures
+        .iter()
+        .map(|failure| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                htmlescape::encode_minimal(&failure.subscriber_email),
+                htmlescape::encode_minimal(&failure.status),
+                failure.n_attempts,
+                htmlescape::encode_minimal(failure.error.as_deref().unwrap_or("")),
+                failure.updated_at.format("%Y-%m-%d %H:%M UTC")
+            )
+        })
+        .collect();
+    let mut pagination = Vec::new();
+    if page > 1 {
+        pagination.push(format!(
+            "<a href=\"/admin/newsletters/{}?page={}\">Previous</a>",
+            newsletter_issue_id,
+            page - 1
+        ));
+    }
+    if has_next_page {
+        pagination.push(format!(
+            "<a href=\"/admin/newsletters/{}?page={}\">Next</a>",
+            newsletter_issue_id,
+            page + 1
+        ));
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("delivery_report.html"),
+            flash_messages = flash_messages.to_html(),
+            title = htmlescape::encode_minimal(&issue.title),
+            published_at = issue.published_at.format("%Y-%m-%d %H:%M UTC"),
+            status_counts = status_counts_html,
+            resume_form = resume_form,
+            failures = failures_html,
+            pagination = pagination.join(" ")
+        )))
+}
+
+#[tracing::instrument(name = "Count deliveries by status", skip(pool))]
+async fn get_status_counts(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<(String, i64)>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT status, COUNT(*) AS "count!"
+        FROM issue_deliveries
+        WHERE newsletter_issue_id = $1
+        GROUP BY status
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to count deliveries by status")?;
+    Ok(rows.into_iter().map(|r| (r.status, r.count)).collect())
+}
+
+#[tracing::instrument(name = "Get failed deliveries", skip(pool))]
+async fn get_failures(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    page: i64,
+) -> Result<Vec<Failure>, anyhow::Error> {
+    let failures = sqlx::query_as!(
+        Failure,
+        r#"
+        SELECT subscriber_email, status, n_attempts, error, updated_at
+        FROM issue_deliveries
+        WHERE newsletter_issue_id = $1 AND status IN ('failed', 'bounced')
+        ORDER BY updated_at DESC, subscriber_email
+        LIMIT $2 OFFSET $3
+        "#,
+        newsletter_issue_id,
+        FAILURES_PER_PAGE + 1,
+        (page - 1).saturating_mul(FAILURES_PER_PAGE)
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve failed deliveries")?;
+    Ok(failures)
+}
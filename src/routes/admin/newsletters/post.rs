@@ -0,0 +1,60 @@
+use crate::authentication::{require_permission, Permission, UserId};
+use crate::flash_messages::FlashMessage;
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Queue the issue again for the recipients whose delivery failed, and only them.
+///
+/// Recipients who got the issue are left alone, as are those whose email was rejected for good.
+pub async fn resume_delivery(
+    newsletter_issue_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    require_permission(&pool, **user_id, Permission::PublishNewsletter).await?;
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let report_url = format!("/admin/newsletters/{}", newsletter_issue_id);
+    let n_resumed = requeue_failed_deliveries(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+    if n_resumed == 0 {
+        FlashMessage::warning("There are no failed deliveries to resume.").send();
+    } else {
+        FlashMessage::info(format!(
+            "Resumed the delivery to {} recipient(s).",
+            n_resumed
+        ))
+        .send();
+    }
+    Ok(see_other(&report_url))
+}
+
+#[tracing::instrument(name = "Requeue failed deliveries", skip(pool))]
+async fn requeue_failed_deliveries(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<u64, anyhow::Error> {
+    // Updating the failed deliveries locks them, so that concurrent resumes queue each only once.
+    let n_resumed = sqlx::query!(
+        r#"
+        WITH resumed AS (
+            UPDATE issue_deliveries
+            SET status = 'queued', updated_at = now()
+            WHERE newsletter_issue_id = $1 AND status = 'failed'
+            RETURNING newsletter_issue_id, subscriber_email
+        )
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        SELECT newsletter_issue_id, subscriber_email FROM resumed
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to requeue failed deliveries")?
+    .rows_affected();
+    Ok(n_resumed)
+}
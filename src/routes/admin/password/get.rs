@@ -0,0 +1,17 @@
+use crate::csrf::CsrfToken;
+use crate::flash_messages::IncomingFlashMessages;
+use actix_web::http::header::ContentType;
+use actix_web::HttpResponse;
+
+pub async fn change_password_form(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("password.html"),
+            flash_messages = flash_messages.to_html(),
+            csrf_field = csrf_token.to_html()
+        ))
+}
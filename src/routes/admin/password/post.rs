@@ -0,0 +1,84 @@
+use crate::authentication::{
+    self, check_password_length, AuthError, Credentials, LoginThrottle, PasswordHashingPolicy,
+    UserId,
+};
+use crate::flash_messages::FlashMessage;
+use crate::routes::get_username;
+use crate::session_state::TypedSession;
+use crate::session_store::PgSessionStore;
+use crate::utils::{e500, see_other, too_many_requests};
+use actix_web::{web, HttpRequest, HttpResponse};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+#[derive(Deserialize)]
+pub struct ChangePasswordFormData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn change_password(
+    request: HttpRequest,
+    form: web::Form<ChangePasswordFormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashingPolicy>,
+    login_throttle: web::Data<LoginThrottle>,
+    session: TypedSession,
+    session_store: web::Data<PgSessionStore>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if let Err(message) = check_new_password(&form.new_password, &form.new_password_check) {
+        FlashMessage::error(message).send();
+        return Ok(see_other("/admin/password"));
+    }
+
+    let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    let credentials = Credentials {
+        username,
+        password: form.0.current_password,
+    };
+    // Throttled like a login, or a hijacked session could guess the current password.
+    let client_ip = login_throttle.client_ip(&request);
+    if let Err(e) = login_throttle
+        .authenticate(&password_hashing, credentials, client_ip)
+        .await
+    {
+        return match e {
+            AuthError::InvalidCredentials(_) => {
+                FlashMessage::error("The current password is incorrect.").send();
+                Ok(see_other("/admin/password"))
+            }
+            AuthError::LockedOut(retry_after) => Ok(too_many_requests(retry_after, e.to_string())),
+            AuthError::UnexpectedError(_) => Err(e500(e)),
+        };
+    }
+
+    authentication::change_password(*user_id, form.0.new_password, &password_hashing, &pool)
+        .await
+        .map_err(e500)?;
+    // Whoever might have been using the old password is kicked out, us included.
+    session_store
+        .invalidate_user_sessions(*user_id)
+        .await
+        .map_err(e500)?;
+    session.log_out();
+    FlashMessage::info("Your password has been changed, please log in again.").send();
+    Ok(see_other("/login"))
+}
+
+/// Make sure a new password was typed the same way twice and has a reasonable length.
+pub(crate) fn check_new_password(
+    new_password: &Secret<String>,
+    new_password_check: &Secret<String>,
+) -> Result<(), String> {
+    if new_password.expose_secret() != new_password_check.expose_secret() {
+        return Err(
+            "You entered two different new passwords - the field values must match.".into(),
+        );
+    }
+    check_password_length(new_password)
+}
@@ -0,0 +1,43 @@
+use crate::authentication::{
+    is_two_factor_enabled, two_factor_enrollment, TotpSecretCipher, UserId,
+};
+use crate::csrf::CsrfToken;
+use crate::flash_messages::IncomingFlashMessages;
+use crate::routes::get_username;
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+pub async fn two_factor_settings(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    cipher: web::Data<TotpSecretCipher>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let body = if is_two_factor_enabled(&pool, *user_id).await.map_err(e500)? {
+        format!(
+            include_str!("enabled.html"),
+            flash_messages = flash_messages.to_html(),
+            csrf_field = csrf_token.to_html()
+        )
+    } else {
+        let username = get_username(*user_id, &pool).await.map_err(e500)?;
+        let enrollment = two_factor_enrollment(&pool, &cipher, *user_id, &username)
+            .await
+            .map_err(e500)?;
+        format!(
+            include_str!("enroll.html"),
+            flash_messages = flash_messages.to_html(),
+            csrf_field = csrf_token.to_html(),
+            qr_code = enrollment.qr_code_svg,
+            provisioning_uri = htmlescape::encode_attribute(&enrollment.provisioning_uri),
+            secret = enrollment.secret
+        )
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
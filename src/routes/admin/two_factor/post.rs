@@ -0,0 +1,77 @@
+use crate::authentication::{
+    disable_two_factor, enable_two_factor, verify_second_factor, AuthError, LoginThrottle,
+    TotpSecretCipher, UserId,
+};
+use crate::flash_messages::FlashMessage;
+use crate::routes::get_username;
+use crate::utils::{e500, see_other, too_many_requests};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+#[derive(Deserialize)]
+pub struct TwoFactorCodeFormData {
+    code: Secret<String>,
+}
+
+pub async fn enable_two_factor_authentication(
+    form: web::Form<TwoFactorCodeFormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    cipher: web::Data<TotpSecretCipher>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let recovery_codes = match enable_two_factor(&pool, &cipher, **user_id, form.0.code).await {
+        Ok(recovery_codes) => recovery_codes,
+        Err(AuthError::InvalidCredentials(_)) => {
+            FlashMessage::error("The code is incorrect, please try again.").send();
+            return Ok(see_other("/admin/two-factor"));
+        }
+        Err(e) => return Err(e500(e)),
+    };
+    let recovery_codes_html: String = recovery_codes
+        .iter()
+        .map(|code| format!("<li><code>{}</code></li>\n", code.expose_secret()))
+        .collect();
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("recovery_codes.html"),
+            recovery_codes = recovery_codes_html
+        )))
+}
+
+pub async fn disable_two_factor_authentication(
+    request: HttpRequest,
+    form: web::Form<TwoFactorCodeFormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    cipher: web::Data<TotpSecretCipher>,
+    login_throttle: web::Data<LoginThrottle>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = **user_id;
+    let username = get_username(user_id, &pool).await.map_err(e500)?;
+    let client_ip = login_throttle.client_ip(&request);
+    let verification = login_throttle
+        .authenticate_second_factor(
+            &username,
+            client_ip,
+            verify_second_factor(&pool, &cipher, user_id, form.0.code),
+        )
+        .await;
+    match verification {
+        Ok(()) => {}
+        Err(AuthError::InvalidCredentials(_)) => {
+            FlashMessage::error("The code is incorrect, please try again.").send();
+            return Ok(see_other("/admin/two-factor"));
+        }
+        Err(e @ AuthError::LockedOut(retry_after)) => {
+            return Ok(too_many_requests(retry_after, e.to_string()));
+        }
+        Err(e @ AuthError::UnexpectedError(_)) => return Err(e500(e)),
+    }
+    disable_two_factor(&pool, user_id).await.map_err(e500)?;
+    FlashMessage::info("Two-factor authentication has been disabled.").send();
+    Ok(see_other("/admin/two-factor"))
+}
@@ -1,7 +1,13 @@
+use crate::csrf::CsrfToken;
+use crate::flash_messages::IncomingFlashMessages;
 use actix_web::{http::header::ContentType, HttpResponse};
 
-pub async fn home() -> HttpResponse {
+pub async fn home(flash_messages: IncomingFlashMessages, csrf_token: CsrfToken) -> HttpResponse {
     HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(include_str!("home.html"))
+        .body(format!(
+            include_str!("home.html"),
+            flash_messages = flash_messages.to_html(),
+            csrf_field = csrf_token.to_html()
+        ))
 }
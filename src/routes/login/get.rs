@@ -1,7 +1,27 @@
-use actix_web::{http::header::ContentType, HttpResponse};
+use crate::authentication::OidcProvider;
+use crate::csrf::CsrfToken;
+use crate::flash_messages::IncomingFlashMessages;
+use actix_web::{http::header::ContentType, web, HttpResponse};
 
-pub async fn login_form() -> HttpResponse {
+pub async fn login_form(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    oidc_provider: Option<web::Data<OidcProvider>>,
+) -> HttpResponse {
+    let oidc_login = oidc_provider
+        .map(|provider| {
+            format!(
+                "<p><a href=\"/login/oidc\">Sign in with {}</a></p>",
+                htmlescape::encode_minimal(provider.name())
+            )
+        })
+        .unwrap_or_default();
     HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(include_str!("login.html"))
+        .body(format!(
+            include_str!("login.html"),
+            flash_messages = flash_messages.to_html(),
+            csrf_field = csrf_token.to_html(),
+            oidc_login = oidc_login
+        ))
 }
@@ -1,5 +1,11 @@
 mod get;
+mod oidc;
+mod password_reset;
 mod post;
+mod two_factor;
 
 pub use get::*;
+pub use oidc::*;
+pub use password_reset::*;
 pub use post::*;
+pub use two_factor::*;
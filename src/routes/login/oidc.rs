@@ -0,0 +1,88 @@
+use crate::authentication::{
+    is_two_factor_enabled, OidcError, OidcProvider, PasswordHashingPolicy,
+};
+use crate::flash_messages::FlashMessage;
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use secrecy::Secret;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+#[derive(Deserialize)]
+pub struct OidcCallbackQuery {
+    code: Option<Secret<String>>,
+    state: Option<String>,
+}
+
+/// Send the editor to the identity provider, if one is configured.
+pub async fn start_oidc_login(
+    provider: Option<web::Data<OidcProvider>>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let provider = match provider {
+        Some(provider) => provider,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let (url, flow) = match provider.authorization_request().await {
+        Ok(request) => request,
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to start an OIDC sign-in");
+            FlashMessage::error(format!(
+                "Signing in with {} is unavailable right now.",
+                provider.name()
+            ))
+            .send();
+            return Ok(see_other("/login"));
+        }
+    };
+    session.insert_oidc_flow(&flow).map_err(e500)?;
+    Ok(see_other(url.as_str()))
+}
+
+/// Where the identity provider sends the editor back to, successful or not.
+pub async fn oidc_callback(
+    query: web::Query<OidcCallbackQuery>,
+    provider: Option<web::Data<OidcProvider>>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashingPolicy>,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    let provider = match provider {
+        Some(provider) => provider,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let flow = session.take_oidc_flow().map_err(e500)?;
+    let query = query.into_inner();
+    let outcome = match (flow, query.code, query.state) {
+        (Some(flow), Some(code), Some(state)) => {
+            provider
+                .sign_in(flow, &state, code, &pool, &password_hashing)
+                .await
+        }
+        _ => Err(OidcError::InvalidFlow(anyhow::anyhow!(
+            "The OIDC callback is missing its code, its state or its session"
+        ))),
+    };
+    let user_id = match outcome {
+        Ok(user_id) => user_id,
+        Err(e @ (OidcError::InvalidFlow(_) | OidcError::NotAllowed)) => {
+            tracing::warn!(error.cause_chain = ?e, "OIDC sign-in refused");
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other("/login"));
+        }
+        Err(e @ OidcError::UnexpectedError(_)) => return Err(e500(e)),
+    };
+
+    let two_factor_enabled = is_two_factor_enabled(&pool, user_id).await.map_err(e500)?;
+
+    // Rotate the session key on privilege change to prevent session fixation.
+    session.renew();
+    // The provider vouches for the identity, not for the second factor set up here.
+    if two_factor_enabled {
+        session.insert_pending_user_id(user_id).map_err(e500)?;
+        return Ok(see_other("/login/two-factor"));
+    }
+    session.insert_user_id(user_id).map_err(e500)?;
+    Ok(see_other("/admin/dashboard"))
+}
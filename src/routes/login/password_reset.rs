@@ -0,0 +1,230 @@
+use crate::authentication::{
+    self, check_password_reset_token, consume_password_reset_token, issue_password_reset_token,
+    AuthError, LoginThrottle, PasswordHashingPolicy, PasswordReset,
+};
+use crate::csrf::CsrfToken;
+use crate::email_client::{EmailClient, EmailError};
+use crate::flash_messages::{FlashMessage, IncomingFlashMessages};
+use crate::routes::check_new_password;
+use crate::session_store::PgSessionStore;
+use crate::startup::{ApplicationBaseUrl, PasswordResetTtl};
+use crate::utils::{e500, see_other, too_many_requests};
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tracing::Instrument;
+
+const RESET_LINK_SENT: &str =
+    "If that account exists and has an email address, a password reset link is on its way.";
+const INVALID_RESET_LINK: &str =
+    "This password reset link is invalid or has expired. Please ask for a new one.";
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordFormData {
+    username: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordQuery {
+    token: Secret<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordFormData {
+    token: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+pub async fn forgot_password_form(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("forgot_password.html"),
+            flash_messages = flash_messages.to_html(),
+            csrf_field = csrf_token.to_html()
+        ))
+}
+
+/// Email a password reset link to the editor, if there is one by that name.
+///
+/// Whatever happens, the response is the same, and the email goes out in the background so that
+/// response times don't give away which usernames exist either. Requests are throttled per
+/// username and per client IP, whether or not the username exists.
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(request, form, pool, email_client, base_url, ttl, login_throttle),
+    fields(username=%form.username)
+)]
+pub async fn request_password_reset(
+    request: HttpRequest,
+    form: web::Form<ForgotPasswordFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    ttl: web::Data<PasswordResetTtl>,
+    login_throttle: web::Data<LoginThrottle>,
+) -> HttpResponse {
+    let client_ip = login_throttle.client_ip(&request);
+    match login_throttle
+        .throttle_password_reset(&form.username, client_ip)
+        .await
+    {
+        Ok(()) => {}
+        Err(AuthError::LockedOut(retry_after)) => {
+            return too_many_requests(
+                retry_after,
+                "Too many password reset requests, please try again later.".into(),
+            );
+        }
+        Err(e) => {
+            // Better no email than an unthrottled one.
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to throttle a password reset request"
+            );
+            FlashMessage::info(RESET_LINK_SENT).send();
+            return see_other("/login");
+        }
+    }
+    match issue_password_reset_token(&pool, &form.username, ttl.0).await {
+        Ok(Some(reset)) => {
+            let task = async move {
+                if let Err(e) = send_password_reset_link(&email_client, &base_url.0, &reset).await {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to send a password reset link"
+                    );
+                }
+            };
+            tokio::spawn(task.in_current_span());
+        }
+        Ok(None) => {}
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to issue a password reset token"
+            );
+        }
+    }
+    FlashMessage::info(RESET_LINK_SENT).send();
+    see_other("/login")
+}
+
+#[tracing::instrument(
+    name = "Send a password reset link",
+    skip(email_client, base_url, reset)
+)]
+async fn send_password_reset_link(
+    email_client: &EmailClient,
+    base_url: &str,
+    reset: &PasswordReset,
+) -> Result<(), EmailError> {
+    let reset_link = format!(
+        "{}/login/reset-password?token={}",
+        base_url,
+        reset.token.expose_secret()
+    );
+    email_client
+        .send_email(
+            &reset.email,
+            "Reset your password",
+            &format!(
+                "Someone asked to reset your password.<br />\
+                Click <a href=\"{}\">here</a> to choose a new one. \
+                If it wasn't you, you can ignore this email.",
+                reset_link
+            ),
+            &format!(
+                "Someone asked to reset your password.\n\
+                Visit {} to choose a new one. \
+                If it wasn't you, you can ignore this email.",
+                reset_link
+            ),
+        )
+        .await
+}
+
+pub async fn reset_password_form(
+    query: web::Query<ResetPasswordQuery>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    if check_password_reset_token(&pool, &query.token)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        FlashMessage::error(INVALID_RESET_LINK).send();
+        return Ok(see_other("/login/forgot-password"));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        // The token is in the URL: keep it out of the Referer of any outgoing request.
+        .insert_header(("Referrer-Policy", "no-referrer"))
+        .body(format!(
+            include_str!("reset_password.html"),
+            flash_messages = flash_messages.to_html(),
+            csrf_field = csrf_token.to_html(),
+            token = htmlescape::encode_attribute(query.token.expose_secret())
+        )))
+}
+
+#[tracing::instrument(
+    name = "Reset a password",
+    skip(form, pool, password_hashing, session_store),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn reset_password(
+    form: web::Form<ResetPasswordFormData>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashingPolicy>,
+    session_store: web::Data<PgSessionStore>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if check_password_reset_token(&pool, &form.token)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        FlashMessage::error(INVALID_RESET_LINK).send();
+        return Ok(see_other("/login/forgot-password"));
+    }
+    // The token matched one we issued, so it is safe to put back in the URL as it is.
+    if let Err(message) = check_new_password(&form.new_password, &form.new_password_check) {
+        FlashMessage::error(message).send();
+        return Ok(see_other(&format!(
+            "/login/reset-password?token={}",
+            form.token.expose_secret()
+        )));
+    }
+    let user_id = match consume_password_reset_token(&pool, &form.token)
+        .await
+        .map_err(e500)?
+    {
+        Some(user_id) => user_id,
+        None => {
+            FlashMessage::error(INVALID_RESET_LINK).send();
+            return Ok(see_other("/login/forgot-password"));
+        }
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(user_id));
+
+    authentication::change_password(user_id, form.0.new_password, &password_hashing, &pool)
+        .await
+        .map_err(e500)?;
+    session_store
+        .invalidate_user_sessions(user_id)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info("Your password has been reset, you can now log in.").send();
+    Ok(see_other("/login"))
+}
@@ -1,5 +1,103 @@
-use actix_web::HttpResponse;
+use crate::authentication::{
+    is_two_factor_enabled, AuthError, Credentials, LoginThrottle, PasswordHashingPolicy,
+};
+use crate::flash_messages::FlashMessage;
+use crate::routes::error_chain_fmt;
+use crate::session_state::TypedSession;
+use crate::utils::{see_other, too_many_requests};
+use actix_web::error::InternalError;
+use actix_web::{web, HttpRequest, HttpResponse};
+use secrecy::Secret;
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::fmt::Formatter;
 
-pub async fn login() -> HttpResponse {
-    HttpResponse::Ok().finish()
+#[derive(Deserialize)]
+pub struct LoginFormData {
+    username: String,
+    password: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum LoginError {
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error("Too many failed login attempts, please try again later.")]
+    LockedOut(std::time::Duration),
+    #[error("Something went wrong")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for LoginError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[tracing::instrument(
+    name = "Log an editor in",
+    skip(request, form, pool, password_hashing, login_throttle, session),
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty,
+        locked_out_for_secs=tracing::field::Empty
+    )
+)]
+pub async fn login(
+    request: HttpRequest,
+    form: web::Form<LoginFormData>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashingPolicy>,
+    login_throttle: web::Data<LoginThrottle>,
+    session: TypedSession,
+) -> Result<HttpResponse, InternalError<LoginError>> {
+    let credentials = Credentials {
+        username: form.0.username,
+        password: form.0.password,
+    };
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let client_ip = login_throttle.client_ip(&request);
+    let user_id = login_throttle
+        .authenticate(&password_hashing, credentials, client_ip)
+        .await
+        .map_err(|e| login_failure(e, "/login"))?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let two_factor_enabled = is_two_factor_enabled(&pool, user_id)
+        .await
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e), "/login"))?;
+
+    // Rotate the session key on privilege change to prevent session fixation.
+    session.renew();
+    if two_factor_enabled {
+        session
+            .insert_pending_user_id(user_id)
+            .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into()), "/login"))?;
+        return Ok(see_other("/login/two-factor"));
+    }
+    session
+        .insert_user_id(user_id)
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into()), "/login"))?;
+    Ok(see_other("/admin/dashboard"))
+}
+
+/// Turn a failed authentication into the matching response: a `429` during a lockout, or
+/// a redirect to `location` otherwise.
+pub(super) fn login_failure(e: AuthError, location: &str) -> InternalError<LoginError> {
+    let e = match e {
+        AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+        AuthError::LockedOut(retry_after) => {
+            tracing::Span::current().record("locked_out_for_secs", retry_after.as_secs());
+            let e = LoginError::LockedOut(retry_after);
+            let response = too_many_requests(retry_after, e.to_string());
+            return InternalError::from_response(e, response);
+        }
+        AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
+    };
+    login_redirect(e, location)
+}
+
+/// Send the editor back to a login form, with a flash message explaining what went wrong.
+pub(super) fn login_redirect(e: LoginError, location: &str) -> InternalError<LoginError> {
+    FlashMessage::error(e.to_string()).send();
+    InternalError::from_response(e, see_other(location))
 }
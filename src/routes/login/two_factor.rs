@@ -0,0 +1,77 @@
+use super::post::{login_failure, login_redirect, LoginError};
+use crate::authentication::{verify_second_factor, LoginThrottle, TotpSecretCipher};
+use crate::csrf::CsrfToken;
+use crate::flash_messages::IncomingFlashMessages;
+use crate::routes::get_username;
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::error::InternalError;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use secrecy::Secret;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+#[derive(Deserialize)]
+pub struct TwoFactorFormData {
+    code: Secret<String>,
+}
+
+pub async fn two_factor_form(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+    session: TypedSession,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_pending_user_id().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            include_str!("two_factor.html"),
+            flash_messages = flash_messages.to_html(),
+            csrf_field = csrf_token.to_html()
+        )))
+}
+
+#[tracing::instrument(
+    name = "Verify an editor's second factor",
+    skip(request, form, pool, cipher, login_throttle, session),
+    fields(user_id=tracing::field::Empty, locked_out_for_secs=tracing::field::Empty)
+)]
+pub async fn verify_two_factor(
+    request: HttpRequest,
+    form: web::Form<TwoFactorFormData>,
+    pool: web::Data<PgPool>,
+    cipher: web::Data<TotpSecretCipher>,
+    login_throttle: web::Data<LoginThrottle>,
+    session: TypedSession,
+) -> Result<HttpResponse, InternalError<LoginError>> {
+    let unexpected = |e: anyhow::Error| login_redirect(LoginError::UnexpectedError(e), "/login");
+    let user_id = match session
+        .get_pending_user_id()
+        .map_err(|e| unexpected(e.into()))?
+    {
+        Some(user_id) => user_id,
+        None => return Ok(see_other("/login")),
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let username = get_username(user_id, &pool).await.map_err(unexpected)?;
+    let client_ip = login_throttle.client_ip(&request);
+    login_throttle
+        .authenticate_second_factor(
+            &username,
+            client_ip,
+            verify_second_factor(&pool, &cipher, user_id, form.0.code),
+        )
+        .await
+        .map_err(|e| login_failure(e, "/login/two-factor"))?;
+
+    session.renew();
+    session.remove_pending_user_id();
+    session
+        .insert_user_id(user_id)
+        .map_err(|e| unexpected(e.into()))?;
+    Ok(see_other("/admin/dashboard"))
+}
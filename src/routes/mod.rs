@@ -1,13 +1,21 @@
+mod admin;
 mod health;
 mod home;
 mod login;
 mod newsletter;
+mod scheduled_issues;
+mod subscribers;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
 
+pub use admin::*;
 pub use health::*;
 pub use home::*;
 pub use login::*;
 pub use newsletter::*;
+pub use scheduled_issues::*;
+pub use subscribers::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
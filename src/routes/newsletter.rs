@@ -1,187 +1,259 @@
-use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::authentication::{
+    basic_authentication, bearer_token, is_two_factor_enabled, require_permission,
+    validate_api_token, AuthError, LoginThrottle, PasswordHashingPolicy, Permission,
+    PermissionError, Scope,
+};
+use crate::domain::NewsletterTemplate;
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::issue_delivery_worker::enqueue_delivery_tasks;
+use crate::startup::IdempotencyKeyTtl;
+use crate::utils::too_many_requests;
 use actix_web::body::BoxBody;
 use actix_web::http::header::{HeaderMap, HeaderValue};
 use actix_web::http::{header, StatusCode};
 use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
-use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
-use secrecy::{ExposeSecret, Secret};
-use serde::Deserialize;
-use sha3::Digest;
-use sqlx::PgPool;
-use std::any::TypeId;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
 use std::error::Error;
 use std::fmt::Formatter;
-use std::str::ParseBoolError;
+use uuid::Uuid;
 
-struct Credentials {
-    username: String,
-    password: Secret<String>,
-}
-
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
-}
-
-#[derive(Deserialize)]
+/// Issues are written and proofread as drafts first: only a saved draft can be published.
+#[derive(Deserialize, Serialize)]
 pub struct NewsletterBody {
-    title: String,
-    content: Content,
+    draft_id: Uuid,
+    /// Schedule the issue for later rather than publishing it right away.
+    publish_at: Option<DateTime<Utc>>,
 }
 
+/// Issues are written in Markdown: the HTML and plain text bodies are rendered from it.
 #[derive(Deserialize)]
 pub struct Content {
-    html: String,
-    text: String,
+    pub(crate) markdown: String,
+}
+
+/// What publishing answers with, so that clients can follow up on the delivery of the issue.
+#[derive(Serialize)]
+struct PublishedIssue {
+    newsletter_issue_id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    publish_at: Option<DateTime<Utc>>,
 }
 
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(request, body, pool, email_client),
-    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+    skip(request, body, pool, password_hashing, login_throttle, idempotency_key_ttl),
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty,
+        locked_out_for_secs=tracing::field::Empty
+    )
 )]
 pub async fn publish_newsletter(
     request: HttpRequest,
     body: web::Json<NewsletterBody>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    password_hashing: web::Data<PasswordHashingPolicy>,
+    login_throttle: web::Data<LoginThrottle>,
+    idempotency_key_ttl: web::Data<IdempotencyKeyTtl>,
 ) -> Result<HttpResponse, PublishError> {
-    let creds = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-    tracing::Span::current().record("username", &tracing::field::display(&creds.username));
-    let user_id = validate_credentials(pool.as_ref(), creds).await?;
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-    let confirmed_subscribers = get_confirmed_subscribers(pool.as_ref()).await?;
-    for subscriber in confirmed_subscribers {
-        email_client
-            .send_email(
-                &subscriber.email,
-                &body.title,
-                &body.content.html,
-                &body.content.text,
-            )
-            .await
-            .with_context(|| {
-                format!("Failed to send newsletter issue to {:?}", subscriber.email)
-            })?;
+    let user_id =
+        authenticate_publisher(&request, &pool, &password_hashing, &login_throttle).await?;
+    let idempotency_key = idempotency_key(request.headers())?;
+    // Hashed as parsed, so that retries differing only in formatting still match.
+    let request_body = serde_json::to_vec(&*body).context("Failed to serialize the request")?;
+    let mut transaction = match try_processing(
+        &pool,
+        &idempotency_key,
+        user_id,
+        &request_body,
+        idempotency_key_ttl.0,
+    )
+    .await?
+    {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+        NextAction::RejectReusedKey => return Err(PublishError::IdempotencyKeyReused),
+    };
+    // Only checked for new requests: a retry gets the saved response even once `publish_at`
+    // has passed.
+    if let Some(publish_at) = body.publish_at {
+        validate_publish_at(publish_at)?;
+    }
+    let issue_id = body.draft_id;
+    publish_draft(&mut transaction, user_id, &body).await?;
+    // Scheduled issues are queued for delivery by the scheduler, once they fall due.
+    if body.publish_at.is_none() {
+        enqueue_delivery_tasks(&mut transaction, issue_id).await?;
     }
-    Ok(HttpResponse::Ok().finish())
+    let response = HttpResponse::Accepted().json(PublishedIssue {
+        newsletter_issue_id: issue_id,
+        publish_at: body.publish_at,
+    });
+    let response = save_response(transaction, &idempotency_key, user_id, response).await?;
+    Ok(response)
 }
 
-async fn get_confirmed_subscribers(
+/// Authenticate the caller of the newsletter API, with an API token or a password, and check that
+/// they may publish.
+pub(crate) async fn authenticate_publisher(
+    request: &HttpRequest,
     pool: &PgPool,
-) -> Result<Vec<ConfirmedSubscriber>, anyhow::Error> {
-    let confirmed_subscribers =
-        sqlx::query!(r#"SELECT email FROM subscriptions WHERE status = 'confirmed'"#,)
-            .fetch_all(pool)
-            .await?
-            .into_iter()
-            .filter_map(|r| match SubscriberEmail::parse(&r.email) {
-                Ok(email) => Some(ConfirmedSubscriber { email }),
-                Err(e) => {
-                    tracing::warn!(
-                        "A confirmed subscriber is using an invalid email address:{}",
-                        e
-                    );
-                    None
-                }
-            })
-            .collect();
-    Ok(confirmed_subscribers)
+    password_hashing: &PasswordHashingPolicy,
+    login_throttle: &LoginThrottle,
+) -> Result<Uuid, PublishError> {
+    let user_id = match bearer_token(request.headers()).map_err(PublishError::AuthError)? {
+        Some(token) => validate_api_token(pool, token, Scope::NewsletterPublish)
+            .await
+            .map_err(PublishError::from_auth_error)?,
+        None => {
+            let creds = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
+            tracing::Span::current().record("username", tracing::field::display(&creds.username));
+            let client_ip = login_throttle.client_ip(request);
+            let user_id = login_throttle
+                .authenticate(password_hashing, creds, client_ip)
+                .await
+                .map_err(PublishError::from_auth_error)?;
+            // A password alone is not enough for editors who opted into two-factor
+            // authentication: they must use an API token.
+            if is_two_factor_enabled(pool, user_id).await? {
+                return Err(PublishError::AuthError(anyhow::anyhow!(
+                    "Two-factor authentication is enabled, an API token is required"
+                )));
+            }
+            user_id
+        }
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    require_permission(pool, user_id, Permission::PublishNewsletter).await?;
+    Ok(user_id)
 }
 
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
-    let header_value = headers
-        .get("Authorization")
-        .context("The 'Authorization' header was missing")?
+pub(crate) fn validate_publish_at(publish_at: DateTime<Utc>) -> Result<(), PublishError> {
+    if publish_at <= Utc::now() {
+        return Err(PublishError::InvalidSchedule(
+            "'publish_at' must be in the future.",
+        ));
+    }
+    Ok(())
+}
+
+/// Retries of the same publish request must carry the same `Idempotency-Key` header, so that
+/// subscribers get the issue only once.
+fn idempotency_key(headers: &HeaderMap) -> Result<IdempotencyKey, PublishError> {
+    let value = headers
+        .get("Idempotency-Key")
+        .ok_or_else(|| {
+            PublishError::InvalidIdempotencyKey(anyhow::anyhow!(
+                "The 'Idempotency-Key' header is missing"
+            ))
+        })?
         .to_str()
-        .context("The 'Authorization' header was not a valid UTF-8 string.")?;
-    let base64encoded_segment = header_value
-        .strip_prefix("Basic ")
-        .context("The authorization scheme was not 'Basic'.")?;
-    let decoded_bytes = base64::decode(base64encoded_segment)
-        .context("Failed to base64-decode 'Basic' credentials")?;
-    let decoded_credentials = String::from_utf8(decoded_bytes)
-        .context("The decoded credential string is not a valid UTF-8.")?;
-    let mut creds = decoded_credentials.splitn(2, ":");
-    let username = creds
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth"))?
-        .to_string();
-    let password = creds
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth"))?
-        .to_string();
-    Ok(Credentials {
-        username,
-        password: Secret::new(password),
-    })
+        .context("The 'Idempotency-Key' header was not a valid UTF-8 string.")
+        .map_err(PublishError::InvalidIdempotencyKey)?;
+    IdempotencyKey::try_from(value.to_string()).map_err(PublishError::InvalidIdempotencyKey)
 }
 
-async fn validate_credentials(
-    pool: &PgPool,
-    credentials: Credentials,
-) -> Result<uuid::Uuid, PublishError> {
-    let current_span = tracing::Span::current();
-    let row: Option<_> = sqlx::query!(
-        r#"SELECT user_id, password_hash FROM editors WHERE username = $1"#,
-        credentials.username
+#[tracing::instrument(skip_all)]
+async fn publish_draft(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    body: &NewsletterBody,
+) -> Result<(), PublishError> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT status, title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        FOR UPDATE
+        "#,
+        body.draft_id
     )
-    .fetch_optional(pool)
+    .fetch_optional(&mut *transaction)
     .await
-    .context("Failed to perform a query to retrieve stored credentials.")
-    .map_err(PublishError::UnexpectedError)?;
-
-    let (expected_password_hash, user_id) = match row {
-        Some(row) => (row.password_hash, row.user_id),
-        None => return Err(PublishError::AuthError(anyhow::anyhow!("Unknown username"))),
+    .context("Failed to retrieve the draft")?;
+    let issue = match issue {
+        None => return Err(PublishError::IssueNotFound),
+        Some(issue) if issue.status != "draft" => return Err(PublishError::NotADraft),
+        Some(issue) => issue,
     };
-    let expected_password_hash = PasswordHash::new(&expected_password_hash)
-        .context("Failed to parse hash in PHC string format")
-        .map_err(PublishError::UnexpectedError)?;
-    let expected_password_hash = Secret::new(expected_password_hash.to_string());
-
-    tokio::task::spawn_blocking(move || {
-        let _ = current_span
-            .in_scope(|| verify_password_hash(expected_password_hash, credentials.password));
-    })
+    // A typo in a template variable must fail here, rather than halfway through the delivery.
+    for template in [&issue.title, &issue.text_content, &issue.html_content] {
+        NewsletterTemplate::parse(template).map_err(PublishError::InvalidContent)?;
+    }
+    let (status, published_at) = match body.publish_at {
+        Some(_) => ("scheduled", None),
+        None => ("published", Some(Utc::now())),
+    };
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = $2, publish_at = $3, published_at = $4, published_by = $5,
+            updated_at = now()
+        WHERE newsletter_issue_id = $1
+        "#,
+        body.draft_id,
+        status,
+        body.publish_at,
+        published_at,
+        user_id
+    )
+    .execute(transaction)
     .await
-    .context("Failed to spawn blocking task")
-    .map_err(PublishError::UnexpectedError)
-    .context("Incorrect password")
-    .map_err(PublishError::AuthError)?;
-
-    Ok(user_id)
-}
-
-#[tracing::instrument(
-    name = "Verify password hash"
-    skip(expected_password_hash, password_candidate)
-)]
-fn verify_password_hash(
-    expected_password_hash: Secret<String>,
-    password_candidate: Secret<String>,
-) -> Result<(), PublishError> {
-    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
-        .context("Failed to parse hash in PHC string format")
-        .map_err(PublishError::UnexpectedError)?;
-    Argon2::default()
-        .verify_password(
-            password_candidate.expose_secret().as_bytes(),
-            &expected_password_hash,
-        )
-        .context("Invalid password")
-        .map_err(PublishError::AuthError)
+    .context("Failed to publish the draft")?;
+    Ok(())
 }
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
     #[error("Authentication failed.")]
     AuthError(#[source] anyhow::Error),
+    #[error("Permission denied.")]
+    Forbidden(#[source] anyhow::Error),
+    #[error("Too many failed login attempts, please try again later.")]
+    LockedOut(std::time::Duration),
+    #[error("A valid 'Idempotency-Key' header is required.")]
+    InvalidIdempotencyKey(#[source] anyhow::Error),
+    #[error("The 'Idempotency-Key' was already used for a different request.")]
+    IdempotencyKeyReused,
+    #[error("{0}")]
+    InvalidSchedule(&'static str),
+    #[error("{0}")]
+    InvalidContent(String),
+    #[error("There is no such newsletter issue.")]
+    IssueNotFound,
+    #[error("The newsletter issue is no longer scheduled.")]
+    NotScheduled,
+    #[error("Only drafts can be published, this issue was published or scheduled already.")]
+    NotADraft,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
+impl PublishError {
+    fn from_auth_error(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(_) => Self::AuthError(e.into()),
+            AuthError::LockedOut(retry_after) => {
+                tracing::Span::current().record("locked_out_for_secs", retry_after.as_secs());
+                Self::LockedOut(retry_after)
+            }
+            AuthError::UnexpectedError(_) => Self::UnexpectedError(e.into()),
+        }
+    }
+}
+
+impl From<PermissionError> for PublishError {
+    fn from(e: PermissionError) -> Self {
+        match e {
+            PermissionError::Denied(_) => Self::Forbidden(e.into()),
+            PermissionError::UnexpectedError(e) => Self::UnexpectedError(e),
+        }
+    }
+}
+
 impl std::fmt::Debug for PublishError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "caused by {:?}", self.source())
@@ -192,6 +264,15 @@ impl ResponseError for PublishError {
     fn error_response(&self) -> HttpResponse<BoxBody> {
         match self {
             Self::UnexpectedError(_) => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+            Self::Forbidden(_) => HttpResponse::new(StatusCode::FORBIDDEN),
+            Self::InvalidIdempotencyKey(_) | Self::InvalidSchedule(_) | Self::InvalidContent(_) => {
+                HttpResponse::BadRequest().body(self.to_string())
+            }
+            Self::IdempotencyKeyReused => {
+                HttpResponse::UnprocessableEntity().body(self.to_string())
+            }
+            Self::IssueNotFound => HttpResponse::NotFound().body(self.to_string()),
+            Self::NotScheduled | Self::NotADraft => HttpResponse::Conflict().body(self.to_string()),
             Self::AuthError(_) => {
                 let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
                 let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
@@ -200,6 +281,7 @@ impl ResponseError for PublishError {
                     .insert(header::WWW_AUTHENTICATE, header_value);
                 response
             }
+            Self::LockedOut(retry_after) => too_many_requests(*retry_after, self.to_string()),
         }
     }
 }
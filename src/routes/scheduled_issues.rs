@@ -0,0 +1,164 @@
+use crate::authentication::{LoginThrottle, PasswordHashingPolicy};
+use crate::domain::{NewsletterContent, NewsletterTemplate};
+use crate::routes::{authenticate_publisher, validate_publish_at, Content, PublishError};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Serialize)]
+struct ScheduledIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    publish_at: DateTime<Utc>,
+    /// `None` once the editor who scheduled the issue is deleted.
+    scheduled_by: Option<String>,
+}
+
+/// The issues waiting for their publication time, soonest first.
+#[tracing::instrument(
+    name = "List scheduled newsletter issues",
+    skip(request, pool, password_hashing, login_throttle),
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty,
+        locked_out_for_secs=tracing::field::Empty
+    )
+)]
+pub async fn list_scheduled_issues(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashingPolicy>,
+    login_throttle: web::Data<LoginThrottle>,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_publisher(&request, &pool, &password_hashing, &login_throttle).await?;
+    let issues = sqlx::query_as!(
+        ScheduledIssue,
+        r#"
+        SELECT newsletter_issue_id, title, publish_at AS "publish_at!",
+            editors.username AS "scheduled_by?"
+        FROM newsletter_issues
+        LEFT JOIN editors ON editors.user_id = newsletter_issues.published_by
+        WHERE status = 'scheduled'
+        ORDER BY publish_at, newsletter_issue_id
+        "#
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .context("Failed to retrieve the scheduled issues")?;
+    Ok(HttpResponse::Ok().json(issues))
+}
+
+#[derive(Deserialize)]
+pub struct ScheduledIssueBody {
+    title: String,
+    content: Content,
+    publish_at: DateTime<Utc>,
+}
+
+/// Replace the content and publication time of an issue, as long as it has not been dispatched.
+#[tracing::instrument(
+    name = "Edit a scheduled newsletter issue",
+    skip(request, body, pool, password_hashing, login_throttle),
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty,
+        locked_out_for_secs=tracing::field::Empty
+    )
+)]
+pub async fn edit_scheduled_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    request: HttpRequest,
+    body: web::Json<ScheduledIssueBody>,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashingPolicy>,
+    login_throttle: web::Data<LoginThrottle>,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_publisher(&request, &pool, &password_hashing, &login_throttle).await?;
+    validate_publish_at(body.publish_at)?;
+    NewsletterTemplate::parse(&body.title).map_err(PublishError::InvalidContent)?;
+    let content =
+        NewsletterContent::parse(&body.content.markdown).map_err(PublishError::InvalidContent)?;
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = $2, markdown_content = $3, text_content = $4, html_content = $5,
+            publish_at = $6, updated_at = now()
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        newsletter_issue_id,
+        body.title,
+        content.markdown(),
+        content.text(),
+        content.html(),
+        body.publish_at
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to update a scheduled issue")?
+    .rows_affected();
+    if updated == 0 {
+        return Err(not_scheduled(&pool, newsletter_issue_id).await?);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Call off the publication of an issue, as long as it has not been dispatched.
+#[tracing::instrument(
+    name = "Cancel a scheduled newsletter issue",
+    skip(request, pool, password_hashing, login_throttle),
+    fields(
+        username=tracing::field::Empty,
+        user_id=tracing::field::Empty,
+        locked_out_for_secs=tracing::field::Empty
+    )
+)]
+pub async fn cancel_scheduled_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    password_hashing: web::Data<PasswordHashingPolicy>,
+    login_throttle: web::Data<LoginThrottle>,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_publisher(&request, &pool, &password_hashing, &login_throttle).await?;
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let cancelled = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'cancelled'
+        WHERE newsletter_issue_id = $1 AND status = 'scheduled'
+        "#,
+        newsletter_issue_id
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to cancel a scheduled issue")?
+    .rows_affected();
+    if cancelled == 0 {
+        return Err(not_scheduled(&pool, newsletter_issue_id).await?);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Tell an unknown issue apart from one that was dispatched or cancelled already.
+async fn not_scheduled(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<PublishError, PublishError> {
+    let exists = sqlx::query!(
+        r#"SELECT 1 AS "exists!" FROM newsletter_issues WHERE newsletter_issue_id = $1"#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a newsletter issue")?
+    .is_some();
+    Ok(if exists {
+        PublishError::NotScheduled
+    } else {
+        PublishError::IssueNotFound
+    })
+}
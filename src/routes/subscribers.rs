@@ -0,0 +1,56 @@
+use crate::authentication::{
+    bearer_token, require_permission, validate_api_token, AuthError, Permission, Scope,
+};
+use crate::utils::e500;
+use actix_web::error::InternalError;
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+
+#[derive(Serialize)]
+pub struct Subscriber {
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "List subscribers",
+    skip(request, pool),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn list_subscribers(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let token = bearer_token(request.headers())
+        .map_err(unauthorized)?
+        .ok_or_else(|| unauthorized(anyhow::anyhow!("An API token is required")))?;
+    let user_id = validate_api_token(&pool, token, Scope::SubscribersRead)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(e) => unauthorized(e),
+            _ => e500(e),
+        })?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    require_permission(&pool, user_id, Permission::ManageSubscribers).await?;
+    let subscribers = sqlx::query_as!(
+        Subscriber,
+        r#"SELECT email, name, status, subscribed_at FROM subscriptions ORDER BY subscribed_at"#
+    )
+    .fetch_all(pool.as_ref())
+    .await
+    .map_err(e500)?;
+    Ok(HttpResponse::Ok().json(subscribers))
+}
+
+/// A `401 Unauthorized` asking for an API token, preserving the root cause for logging.
+fn unauthorized(e: anyhow::Error) -> actix_web::Error {
+    let response = HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, r#"Bearer realm="subscribers""#))
+        .finish();
+    InternalError::from_response(e, response).into()
+}
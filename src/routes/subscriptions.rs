@@ -1,7 +1,9 @@
 use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
-use actix_web::http::StatusCode;
+use crate::flash_messages::FlashMessage;
+use crate::utils::see_other;
+use actix_web::http::{header, StatusCode};
 use actix_web::web::Form;
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
@@ -10,7 +12,7 @@ use sqlx::types::chrono::Utc;
 use sqlx::{PgPool, Postgres, Transaction};
 use std::fmt::{Debug, Display, Formatter};
 
-use crate::email_client::EmailClient;
+use crate::email_client::{EmailClient, EmailError};
 use crate::startup::ApplicationBaseUrl;
 use uuid::Uuid;
 
@@ -61,24 +63,51 @@ impl From<String> for SubscribeError {
 
 #[tracing::instrument(
 name = "Adding a new subscriber",
-skip(form, pool, email_client),
+skip(request, form, pool, email_client),
 fields(
 subscriber_email = % form.email,
 subscriber_name = % form.name
 )
 )]
 pub async fn subscribe(
+    request: HttpRequest,
     form: Form<FormData>,
     pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
 ) -> Result<HttpResponse, SubscribeError> {
+    let outcome = register_subscriber(form.0, &pool, &email_client, &base_url.0).await;
+    if !accepts_html(&request) {
+        return outcome.map(|_| HttpResponse::Ok().finish());
+    }
+
+    // Browsers submitting the form on the home page get redirected back to it, with a
+    // flash message telling them how it went.
+    match outcome {
+        Ok(()) => FlashMessage::info(
+            "Thanks for subscribing! Check your inbox to confirm your subscription.",
+        )
+        .send(),
+        Err(SubscribeError::ValidationError(e)) => FlashMessage::error(e).send(),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to register a new subscriber");
+            FlashMessage::error("Something went wrong, please try again later.").send()
+        }
+    }
+    Ok(see_other("/"))
+}
+
+async fn register_subscriber(
+    form: FormData,
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+) -> Result<(), SubscribeError> {
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
-    let new_subscriber: NewSubscriber =
-        form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let new_subscriber: NewSubscriber = form.try_into().map_err(SubscribeError::ValidationError)?;
     let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
         .await
         .context("Failed to insert subscriber to database")?;
@@ -90,16 +119,19 @@ pub async fn subscribe(
         .commit()
         .await
         .context("Failed to commit the transaction [store a new subscriber to db]")?;
-    send_confirmation_link(
-        &email_client,
-        &new_subscriber,
-        &base_url.0,
-        &subscription_token,
-    )
-    .await
-    .context("Failed to send confirmation to new subscriber")?;
+    send_confirmation_link(email_client, &new_subscriber, base_url, &subscription_token)
+        .await
+        .context("Failed to send confirmation to new subscriber")?;
+    Ok(())
+}
 
-    Ok(HttpResponse::Ok().finish())
+fn accepts_html(request: &HttpRequest) -> bool {
+    request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.contains("text/html"))
+        .unwrap_or(false)
 }
 
 #[tracing::instrument(
@@ -135,7 +167,7 @@ pub async fn send_confirmation_link(
     new_subscriber: &NewSubscriber,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), EmailError> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
@@ -178,7 +210,7 @@ async fn store_subscription_token(
     Ok(())
 }
 
-fn generate_subscription_token() -> String {
+pub(crate) fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -206,7 +238,10 @@ impl std::error::Error for StoreTokenError {
     }
 }
 
-fn error_chain_fmt(e: &impl std::error::Error, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+pub fn error_chain_fmt(
+    e: &impl std::error::Error,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
     let _ = writeln!(f, "{}\n", e);
     let mut current = e.source();
     while let Some(cause) = current {
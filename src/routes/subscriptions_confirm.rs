@@ -18,12 +18,14 @@ pub async fn confirm_sub(params: web::Query<Parameters>, pool: web::Data<PgPool>
 
     match subscriber_id {
         None => HttpResponse::Unauthorized().finish(),
-        Some(id) => {
-            if confirm_subscriber_id(pool.as_ref(), id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
+        Some(id) => match confirm_subscriber_id(pool.as_ref(), id).await {
+            Err(_) => HttpResponse::InternalServerError().finish(),
+            // An old confirmation link can't undo unsubscribing.
+            Ok(false) => {
+                HttpResponse::Conflict().body("You have unsubscribed from the newsletter.")
             }
-            HttpResponse::Ok().finish()
-        }
+            Ok(true) => HttpResponse::Ok().finish(),
+        },
     }
 }
 
@@ -45,13 +47,17 @@ async fn get_subscriber_id_from_token(
     Ok(result.map(|r| r.subscriber_id))
 }
 
+/// Whether the subscriber is confirmed: those who unsubscribed stay so.
 #[tracing::instrument(
     name = "Mark a new subscriber as confirmed in db",
     skip(pool, subscriber_id)
 )]
-async fn confirm_subscriber_id(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+async fn confirm_subscriber_id(pool: &PgPool, subscriber_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'confirmed'
+        WHERE id = $1 AND status != 'unsubscribed'
+        "#,
         subscriber_id
     )
     .execute(pool)
@@ -60,5 +66,5 @@ async fn confirm_subscriber_id(pool: &PgPool, subscriber_id: Uuid) -> Result<(),
         tracing::error!("failed to execute query: {:?}", e);
         e
     })?;
-    Ok(())
+    Ok(result.rows_affected() > 0)
 }
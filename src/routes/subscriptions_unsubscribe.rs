@@ -0,0 +1,68 @@
+use crate::csrf::CsrfToken;
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+const UNSUBSCRIBED: &str = "You have been unsubscribed.";
+const UNKNOWN_TOKEN: &str = "This unsubscribe link is not valid.";
+
+#[derive(Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+/// Where the `{{ unsubscribe_url }}` of newsletter issues leads: a page asking the subscriber to
+/// confirm, since mail scanners and link previews follow links on their own.
+#[tracing::instrument(name = "Show the unsubscribe form", skip(params, pool, csrf_token))]
+pub async fn unsubscribe_form(
+    params: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    let status = sqlx::query!(
+        "SELECT status FROM subscriptions WHERE unsubscribe_token = $1",
+        params.token,
+    )
+    .fetch_optional(pool.as_ref())
+    .await
+    .context("Failed to look up an unsubscribe token")
+    .map_err(e500)?
+    .map(|row| row.status);
+    Ok(match status.as_deref() {
+        None => HttpResponse::NotFound().body(UNKNOWN_TOKEN),
+        Some("unsubscribed") => HttpResponse::Ok().body(UNSUBSCRIBED),
+        Some(_) => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            // The token is in the URL: keep it out of the Referer of any outgoing request.
+            .insert_header(("Referrer-Policy", "no-referrer"))
+            .body(format!(
+                include_str!("unsubscribe.html"),
+                csrf_field = csrf_token.to_html(),
+                token = htmlescape::encode_attribute(&params.token)
+            )),
+    })
+}
+
+/// No further issue is delivered to the subscriber. Unsubscribing twice succeeds.
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(form, pool))]
+pub async fn unsubscribe(
+    form: web::Form<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let unsubscribed = sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed' WHERE unsubscribe_token = $1",
+        form.token,
+    )
+    .execute(pool.as_ref())
+    .await
+    .context("Failed to unsubscribe a subscriber")
+    .map_err(e500)?
+    .rows_affected();
+    if unsubscribed == 0 {
+        return Ok(HttpResponse::NotFound().body(UNKNOWN_TOKEN));
+    }
+    Ok(HttpResponse::Ok().body(UNSUBSCRIBED))
+}
@@ -0,0 +1,81 @@
+use crate::authentication::OidcFlow;
+use crate::csrf::CsrfRotation;
+use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest};
+use std::future::{ready, Ready};
+use uuid::Uuid;
+
+/// The name of the cookie holding the session key.
+pub const SESSION_COOKIE_NAME: &str = "id";
+
+/// A strongly-typed view over the editor's session.
+///
+/// Logging in or out also replaces the CSRF token, so that a token planted in the browser
+/// beforehand can't be used against the new session.
+pub struct TypedSession {
+    session: Session,
+    csrf_rotation: CsrfRotation,
+}
+
+impl TypedSession {
+    pub const USER_ID_KEY: &'static str = "user_id";
+    /// Set once the password has been verified for an editor with two-factor authentication,
+    /// until they provide their second factor.
+    pub const PENDING_USER_ID_KEY: &'static str = "pending_user_id";
+    /// Set while the editor signs in at the OpenID Connect provider.
+    pub const OIDC_FLOW_KEY: &'static str = "oidc_flow";
+
+    pub fn renew(&self) {
+        self.session.renew();
+        self.csrf_rotation.rotate();
+    }
+
+    pub fn insert_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.session.insert(Self::USER_ID_KEY, user_id)
+    }
+
+    pub fn get_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.session.get(Self::USER_ID_KEY)
+    }
+
+    pub fn insert_pending_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.session.insert(Self::PENDING_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_pending_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.session.get(Self::PENDING_USER_ID_KEY)
+    }
+
+    pub fn remove_pending_user_id(&self) {
+        self.session.remove(Self::PENDING_USER_ID_KEY);
+    }
+
+    pub fn insert_oidc_flow(&self, flow: &OidcFlow) -> Result<(), SessionInsertError> {
+        self.session.insert(Self::OIDC_FLOW_KEY, flow)
+    }
+
+    /// Get the pending OpenID Connect flow, which can only be used once.
+    pub fn take_oidc_flow(&self) -> Result<Option<OidcFlow>, SessionGetError> {
+        let flow = self.session.get(Self::OIDC_FLOW_KEY)?;
+        self.session.remove(Self::OIDC_FLOW_KEY);
+        Ok(flow)
+    }
+
+    pub fn log_out(self) {
+        self.session.purge();
+        self.csrf_rotation.rotate();
+    }
+}
+
+impl FromRequest for TypedSession {
+    type Error = <Session as FromRequest>::Error;
+    type Future = Ready<Result<TypedSession, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(TypedSession {
+            session: req.get_session(),
+            csrf_rotation: CsrfRotation::of(req),
+        }))
+    }
+}
@@ -0,0 +1,184 @@
+use crate::session_state::TypedSession;
+use actix_session::storage::{
+    generate_session_key, LoadError, SaveError, SessionKey, SessionStore, UpdateError,
+};
+use actix_web::cookie::time::Duration;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::time::Duration as StdDuration;
+use uuid::Uuid;
+
+type SessionState = HashMap<String, String>;
+
+/// Server-side session storage backed by the `sessions` table.
+///
+/// Keeping the state in Postgres lets sessions survive restarts and be shared across every
+/// instance behind the load balancer, and allows us to invalidate them from the server.
+#[derive(Clone)]
+pub struct PgSessionStore {
+    pool: PgPool,
+}
+
+impl PgSessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Log an editor out everywhere by dropping every session that belongs to them.
+    #[tracing::instrument(name = "Invalidate all sessions of an editor", skip(self))]
+    pub async fn invalidate_user_sessions(&self, user_id: Uuid) -> Result<(), anyhow::Error> {
+        sqlx::query!(r#"DELETE FROM sessions WHERE user_id = $1"#, user_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete the sessions of an editor")?;
+        Ok(())
+    }
+
+    /// Expired sessions are never loaded, this only reclaims their rows.
+    #[tracing::instrument(name = "Delete expired sessions", skip(self))]
+    pub async fn delete_expired_sessions(&self) -> Result<(), anyhow::Error> {
+        let deleted = sqlx::query!(r#"DELETE FROM sessions WHERE expires_at < now()"#)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete expired sessions")?
+            .rows_affected();
+        tracing::info!(deleted, "Deleted expired sessions");
+        Ok(())
+    }
+}
+
+/// Periodically delete the sessions that expired.
+pub async fn run_session_expiry_until_stopped(store: PgSessionStore, interval: StdDuration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        if let Err(e) = store.delete_expired_sessions().await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to delete expired sessions"
+            );
+        }
+    }
+}
+
+impl SessionStore for PgSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let row = sqlx::query!(
+            r#"SELECT state FROM sessions WHERE session_key = $1 AND expires_at > now()"#,
+            session_key.as_ref()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load session state")
+        .map_err(LoadError::Other)?;
+        row.map(|r| serde_json::from_value(r.state))
+            .transpose()
+            .context("Failed to deserialize session state")
+            .map_err(LoadError::Deserialization)
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_session_key();
+        let user_id = session_user_id(&session_state);
+        let state = serde_json::to_value(session_state)
+            .context("Failed to serialize session state")
+            .map_err(SaveError::Serialization)?;
+        let now = Utc::now();
+        sqlx::query!(
+            r#"
+            INSERT INTO sessions (session_key, user_id, state, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            session_key.as_ref(),
+            user_id,
+            state,
+            now,
+            now + chrono::Duration::seconds(ttl.whole_seconds())
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to save session state")
+        .map_err(SaveError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let user_id = session_user_id(&session_state);
+        let state = serde_json::to_value(&session_state)
+            .context("Failed to serialize session state")
+            .map_err(UpdateError::Serialization)?;
+        let result = sqlx::query!(
+            r#"
+            UPDATE sessions SET user_id = $2, state = $3, expires_at = $4
+            WHERE session_key = $1 AND expires_at > now()
+            "#,
+            session_key.as_ref(),
+            user_id,
+            state,
+            Utc::now() + chrono::Duration::seconds(ttl.whole_seconds())
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update session state")
+        .map_err(UpdateError::Other)?;
+
+        if result.rows_affected() == 0 {
+            // The session expired or was invalidated in the meantime: start a fresh one.
+            self.save(session_state, ttl).await.map_err(|e| match e {
+                SaveError::Serialization(e) => UpdateError::Serialization(e),
+                SaveError::Other(e) => UpdateError::Other(e),
+            })
+        } else {
+            Ok(session_key)
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"UPDATE sessions SET expires_at = $2 WHERE session_key = $1"#,
+            session_key.as_ref(),
+            Utc::now() + chrono::Duration::seconds(ttl.whole_seconds())
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update session TTL")?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"DELETE FROM sessions WHERE session_key = $1"#,
+            session_key.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete session")?;
+        Ok(())
+    }
+}
+
+/// The editor a session belongs to, tracked in its own column so that we can invalidate
+/// sessions without deserializing every row.
+///
+/// Sessions halfway through a two-factor login belong to the editor too.
+fn session_user_id(session_state: &SessionState) -> Option<Uuid> {
+    session_state
+        .get(TypedSession::USER_ID_KEY)
+        .or_else(|| session_state.get(TypedSession::PENDING_USER_ID_KEY))
+        .and_then(|value| serde_json::from_str(value).ok())
+}
@@ -1,23 +1,64 @@
 use std::net::TcpListener;
 
-use crate::configuration::{DatabaseSettings, Settings};
+use crate::authentication::{
+    reject_anonymous_users, LoginThrottle, OidcProvider, PasswordHashingPolicy, TotpSecretCipher,
+};
+use crate::configuration::{
+    ApplicationSettings, DatabaseSettings, IdempotencySettings, IssueDeliverySettings,
+    IssueSchedulingSettings, Settings,
+};
+use crate::csrf::{csrf_protection, CsrfKey};
 use crate::email_client::EmailClient;
+use crate::flash_messages::{flash_messages_framework, FlashMessagesKey};
+use crate::idempotency::run_expiry_until_stopped;
+use crate::issue_delivery_worker::run_workers_until_stopped;
+use crate::issue_scheduler::run_scheduler_until_stopped;
+use crate::session_state::SESSION_COOKIE_NAME;
+use crate::session_store::{run_session_expiry_until_stopped, PgSessionStore};
+use actix_session::config::{PersistentSession, TtlExtensionPolicy};
+use actix_session::SessionMiddleware;
+use actix_web::cookie::Key;
 use actix_web::dev::Server;
+use actix_web::middleware::from_fn;
 use actix_web::{web, App, HttpServer};
+use secrecy::ExposeSecret;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use tracing_actix_web::TracingLogger;
 
 use crate::routes::{
-    confirm_sub, health_check, home, login, login_form, publish_newsletter, subscribe,
+    admin_dashboard, api_tokens, cancel_scheduled_issue, change_editor_role, change_password,
+    change_password_form, confirm_sub, create_api_token_form, create_draft, delivery_report,
+    disable_two_factor_authentication, draft, draft_preview, drafts, edit_scheduled_issue, editors,
+    enable_two_factor_authentication, forgot_password_form, health_check, home,
+    list_scheduled_issues, list_subscribers, log_out, login, login_form, newsletter_issues,
+    oidc_callback, publish_newsletter, request_password_reset, reset_password, reset_password_form,
+    resume_delivery, revoke_api_token_form, send_test_email, start_oidc_login, subscribe,
+    two_factor_form, two_factor_settings, unsubscribe, unsubscribe_form, update_draft,
+    verify_two_factor, DRAFT_FORM_LIMIT,
 };
 
 #[derive(Debug)]
 pub struct ApplicationBaseUrl(pub String);
 
+/// How long a password reset link stays usable.
+#[derive(Debug)]
+pub struct PasswordResetTtl(pub std::time::Duration);
+
+/// How long a response is replayed to requests reusing its idempotency key.
+#[derive(Debug)]
+pub struct IdempotencyKeyTtl(pub std::time::Duration);
+
 pub struct Application {
     server: Server,
     port: u16,
+    pool: PgPool,
+    email_client: EmailClient,
+    idempotency: IdempotencySettings,
+    issue_delivery: IssueDeliverySettings,
+    issue_scheduling: IssueSchedulingSettings,
+    base_url: String,
+    session_expiry_interval: std::time::Duration,
 }
 
 impl Application {
@@ -35,6 +76,18 @@ impl Application {
             auth_token,
             timeout,
         );
+        let password_hashing = configuration
+            .password_hashing
+            .policy()
+            .expect("invalid password hashing settings");
+        let login_throttle =
+            LoginThrottle::new(connection_pool.clone(), &configuration.login_throttling);
+        let oidc_provider = configuration
+            .oidc
+            .clone()
+            .map(|settings| OidcProvider::new(settings, &configuration.application.base_url))
+            .transpose()
+            .expect("invalid OIDC settings");
         let addr = format!(
             "{}:{}",
             configuration.application.host, configuration.application.port
@@ -43,44 +96,196 @@ impl Application {
         let port = listener.local_addr().unwrap().port();
         let server = Self::run(
             listener,
-            connection_pool,
-            email_client,
-            &configuration.application.base_url,
+            connection_pool.clone(),
+            email_client.clone(),
+            password_hashing,
+            login_throttle,
+            oidc_provider,
+            &configuration.application,
+            &configuration.idempotency,
         )
         .await?;
-        Ok(Self { server, port })
+        Ok(Self {
+            server,
+            port,
+            pool: connection_pool,
+            email_client,
+            idempotency: configuration.idempotency.clone(),
+            issue_delivery: configuration.issue_delivery.clone(),
+            issue_scheduling: configuration.issue_scheduling.clone(),
+            base_url: configuration.application.base_url.clone(),
+            session_expiry_interval: configuration.application.session_expiry_interval(),
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// Serve requests, running background jobs alongside until the server stops.
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        tokio::select! {
+            outcome = self.server => outcome,
+            _ = run_expiry_until_stopped(self.pool.clone(), self.idempotency) => Ok(()),
+            _ = run_session_expiry_until_stopped(
+                PgSessionStore::new(self.pool.clone()),
+                self.session_expiry_interval,
+            ) => Ok(()),
+            _ = run_scheduler_until_stopped(self.pool.clone(), self.issue_scheduling) => Ok(()),
+            _ = run_workers_until_stopped(
+                self.pool,
+                self.email_client,
+                self.issue_delivery,
+                self.base_url,
+            ) => Ok(()),
+        }
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn run(
         listener: TcpListener,
         pool: PgPool,
         client: EmailClient,
-        base_url: &str,
+        password_hashing: PasswordHashingPolicy,
+        login_throttle: LoginThrottle,
+        oidc_provider: Option<OidcProvider>,
+        settings: &ApplicationSettings,
+        idempotency: &IdempotencySettings,
     ) -> Result<Server, std::io::Error> {
-        let base_url = web::Data::new(ApplicationBaseUrl(base_url.to_string()));
+        let base_url = web::Data::new(ApplicationBaseUrl(settings.base_url.clone()));
+        let password_reset_ttl = web::Data::new(PasswordResetTtl(settings.password_reset_ttl()));
+        let idempotency_key_ttl = web::Data::new(IdempotencyKeyTtl(idempotency.key_ttl()));
+        let secret_key = Key::from(settings.hmac_secret.expose_secret().as_bytes());
+        let session_store = PgSessionStore::new(pool.clone());
+        let session_ttl = settings.session_ttl();
+        let totp_cipher = TotpSecretCipher::parse(&settings.totp_encryption_key)
+            .expect("invalid TOTP encryption key");
+        let totp_cipher = web::Data::new(totp_cipher);
+        let sessions = web::Data::new(session_store.clone());
+        let flash_messages_key = web::Data::new(FlashMessagesKey(secret_key.clone()));
+        let csrf_key = web::Data::new(CsrfKey(secret_key.clone()));
         let pool = web::Data::new(pool);
         let client = web::Data::new(client);
+        let password_hashing = web::Data::new(password_hashing);
+        let login_throttle = web::Data::new(login_throttle);
+        let oidc_provider = oidc_provider.map(web::Data::new);
         let server = HttpServer::new(move || {
-            App::new()
+            let app = App::new()
+                .wrap(from_fn(csrf_protection))
+                .wrap(from_fn(flash_messages_framework))
+                .wrap(
+                    SessionMiddleware::builder(session_store.clone(), secret_key.clone())
+                        .cookie_name(SESSION_COOKIE_NAME.into())
+                        .session_lifecycle(
+                            PersistentSession::default()
+                                .session_ttl(session_ttl)
+                                .session_ttl_extension_policy(TtlExtensionPolicy::OnEveryRequest),
+                        )
+                        .build(),
+                )
                 .wrap(TracingLogger::default())
                 .route("/health_check", web::get().to(health_check))
                 .route("/subscriptions", web::post().to(subscribe))
+                .route("/subscriptions", web::get().to(list_subscribers))
                 .route("/subscriptions/confirm", web::get().to(confirm_sub))
+                .route(
+                    "/subscriptions/unsubscribe",
+                    web::get().to(unsubscribe_form),
+                )
+                .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
                 .route("/newsletter", web::post().to(publish_newsletter))
+                .route(
+                    "/newsletter/scheduled",
+                    web::get().to(list_scheduled_issues),
+                )
+                .route(
+                    "/newsletter/scheduled/{newsletter_issue_id}",
+                    web::put().to(edit_scheduled_issue),
+                )
+                .route(
+                    "/newsletter/scheduled/{newsletter_issue_id}",
+                    web::delete().to(cancel_scheduled_issue),
+                )
                 .route("/", web::get().to(home))
                 .route("/login", web::get().to(login_form))
                 .route("/login", web::post().to(login))
+                .route("/login/two-factor", web::get().to(two_factor_form))
+                .route("/login/oidc", web::get().to(start_oidc_login))
+                .route("/login/oidc/callback", web::get().to(oidc_callback))
+                .route("/login/two-factor", web::post().to(verify_two_factor))
+                .route(
+                    "/login/forgot-password",
+                    web::get().to(forgot_password_form),
+                )
+                .route(
+                    "/login/forgot-password",
+                    web::post().to(request_password_reset),
+                )
+                .route("/login/reset-password", web::get().to(reset_password_form))
+                .route("/login/reset-password", web::post().to(reset_password))
+                .service(
+                    web::scope("/admin")
+                        .wrap(from_fn(reject_anonymous_users))
+                        .route("/dashboard", web::get().to(admin_dashboard))
+                        .route("/password", web::get().to(change_password_form))
+                        .route("/password", web::post().to(change_password))
+                        .route("/two-factor", web::get().to(two_factor_settings))
+                        .route(
+                            "/two-factor/enable",
+                            web::post().to(enable_two_factor_authentication),
+                        )
+                        .route(
+                            "/two-factor/disable",
+                            web::post().to(disable_two_factor_authentication),
+                        )
+                        .route("/api-tokens", web::get().to(api_tokens))
+                        .route("/api-tokens", web::post().to(create_api_token_form))
+                        .route(
+                            "/api-tokens/{token_id}/revoke",
+                            web::post().to(revoke_api_token_form),
+                        )
+                        .route("/editors", web::get().to(editors))
+                        .service(
+                            web::scope("/drafts")
+                                .app_data(web::FormConfig::default().limit(DRAFT_FORM_LIMIT))
+                                .route("", web::get().to(drafts))
+                                .route("", web::post().to(create_draft))
+                                .route("/{draft_id}", web::get().to(draft))
+                                .route("/{draft_id}", web::post().to(update_draft))
+                                .route("/{draft_id}/preview", web::get().to(draft_preview))
+                                .route("/{draft_id}/test", web::post().to(send_test_email)),
+                        )
+                        .route("/newsletters", web::get().to(newsletter_issues))
+                        .route(
+                            "/newsletters/{newsletter_issue_id}",
+                            web::get().to(delivery_report),
+                        )
+                        .route(
+                            "/newsletters/{newsletter_issue_id}/resume",
+                            web::post().to(resume_delivery),
+                        )
+                        .route(
+                            "/editors/{editor_id}/role",
+                            web::post().to(change_editor_role),
+                        )
+                        .route("/logout", web::post().to(log_out)),
+                )
                 .app_data(pool.clone())
                 .app_data(client.clone())
+                .app_data(password_hashing.clone())
+                .app_data(login_throttle.clone())
+                .app_data(totp_cipher.clone())
                 .app_data(base_url.clone())
+                .app_data(password_reset_ttl.clone())
+                .app_data(idempotency_key_ttl.clone())
+                .app_data(sessions.clone())
+                .app_data(flash_messages_key.clone())
+                .app_data(csrf_key.clone());
+            // Handlers check for the provider to know whether single sign-on is enabled.
+            match &oidc_provider {
+                Some(oidc_provider) => app.app_data(oidc_provider.clone()),
+                None => app,
+            }
         })
         .listen(listener)?
         .run();
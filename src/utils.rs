@@ -0,0 +1,27 @@
+use actix_web::http::header::{LOCATION, RETRY_AFTER};
+use actix_web::HttpResponse;
+
+/// Redirect to `location` with a `303 See Other`, so that the browser follows up with a GET.
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
+
+/// Refuse the request with a `429 Too Many Requests`, telling the client how many seconds to
+/// wait before trying again.
+pub fn too_many_requests(retry_after: std::time::Duration, message: String) -> HttpResponse {
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    HttpResponse::TooManyRequests()
+        .insert_header((RETRY_AFTER, seconds.max(1)))
+        .body(message)
+}
+
+/// Turn an unexpected failure into a `500 Internal Server Error`, preserving its root cause
+/// for logging.
+pub fn e500<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorInternalServerError(e)
+}
@@ -0,0 +1,153 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use kobo::cli::AdminCommand;
+use uuid::Uuid;
+
+fn generated_password(output: &str) -> String {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("Password: "))
+        .expect("No generated password in the output")
+        .to_string()
+}
+
+async fn create_editor(app: &TestApp, role: &str) -> (String, String) {
+    let username = Uuid::new_v4().to_string();
+    let output = app
+        .run_admin(AdminCommand::CreateEditor {
+            username: username.clone(),
+            role: role.into(),
+            email: None,
+            password_stdin: false,
+        })
+        .await
+        .unwrap();
+    (username, generated_password(&output))
+}
+
+async fn post_login(app: &TestApp, username: &str, password: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": username,
+        "password": password
+    }))
+    .await
+}
+
+#[tokio::test]
+async fn created_editors_can_log_in_with_the_generated_password() {
+    let app = spawn_app().await;
+    let (username, password) = create_editor(&app, "publisher").await;
+
+    let response = post_login(&app, &username, &password).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_html("/admin/dashboard").await;
+    assert!(html_page.contains("Your role: publisher"));
+}
+
+#[tokio::test]
+async fn creating_an_editor_with_an_unknown_role_fails() {
+    let app = spawn_app().await;
+
+    let result = app
+        .run_admin(AdminCommand::CreateEditor {
+            username: "someone".into(),
+            role: "superuser".into(),
+            email: None,
+            password_stdin: false,
+        })
+        .await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn list_editors_shows_every_editor_and_their_status() {
+    let app = spawn_app().await;
+    let (username, _) = create_editor(&app, "author").await;
+    app.run_admin(AdminCommand::DisableEditor {
+        username: username.clone(),
+    })
+    .await
+    .unwrap();
+
+    let output = app.run_admin(AdminCommand::ListEditors).await.unwrap();
+
+    assert!(output.contains(&app.test_user.username));
+    let line = output.lines().find(|l| l.contains(&username)).unwrap();
+    assert!(line.contains("author"));
+    assert!(line.ends_with("disabled"));
+}
+
+#[tokio::test]
+async fn disabled_editors_are_logged_out_and_cannot_log_in_again() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    app.run_admin(AdminCommand::DisableEditor {
+        username: app.test_user.username.clone(),
+    })
+    .await
+    .unwrap();
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    let response = post_login(&app, &app.test_user.username, &app.test_user.password).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "markdown": "Newsletter body",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.run_admin(AdminCommand::EnableEditor {
+        username: app.test_user.username.clone(),
+    })
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+}
+
+#[tokio::test]
+async fn reset_password_replaces_the_old_password() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let output = app
+        .run_admin(AdminCommand::ResetPassword {
+            username: app.test_user.username.clone(),
+            password_stdin: false,
+        })
+        .await
+        .unwrap();
+    let new_password = generated_password(&output);
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    let response = post_login(&app, &app.test_user.username, &app.test_user.password).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = post_login(&app, &app.test_user.username, &new_password).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn deleted_editors_are_gone() {
+    let app = spawn_app().await;
+    let (username, password) = create_editor(&app, "viewer").await;
+
+    app.run_admin(AdminCommand::DeleteEditor {
+        username: username.clone(),
+    })
+    .await
+    .unwrap();
+
+    let output = app.run_admin(AdminCommand::ListEditors).await.unwrap();
+    assert!(!output.contains(&username));
+    let response = post_login(&app, &username, &password).await;
+    assert_is_redirect_to(&response, "/login");
+    let result = app.run_admin(AdminCommand::DeleteEditor { username }).await;
+    assert!(result.is_err());
+}
@@ -0,0 +1,161 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "markdown": "Newsletter body",
+        }
+    })
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_api_tokens() {
+    let app = spawn_app().await;
+    let response = app
+        .post_api_tokens(&serde_json::json!({ "name": "CI", "newsletter:publish": "on" }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn newsletters_can_be_published_with_a_bearer_token() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token(&["newsletter:publish"]).await;
+    assert!(token.starts_with("kobo_"));
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let html_page = app.get_html("/admin/api-tokens").await;
+    assert!(html_page.contains("<td>Never</td>"));
+
+    let draft_id = app.create_draft(&newsletter_request_body()).await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletter", &app.addr))
+        .bearer_auth(&token)
+        .header("Idempotency-Key", Uuid::new_v4().to_string())
+        .json(&serde_json::json!({ "draft_id": draft_id }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 202);
+
+    let html_page = app.get_html("/admin/api-tokens").await;
+    assert!(!html_page.contains("<td>Never</td>"));
+}
+
+#[tokio::test]
+async fn tokens_are_stored_hashed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token(&["newsletter:publish"]).await;
+
+    let stored_hash = sqlx::query!("SELECT token_hash FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .token_hash;
+    assert_ne!(stored_hash, token);
+    assert!(!stored_hash.contains(&token));
+}
+
+#[tokio::test]
+async fn tokens_without_the_publish_scope_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token(&["subscribers:read"]).await;
+
+    let draft_id = app.create_draft(&newsletter_request_body()).await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletter", &app.addr))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({ "draft_id": draft_id }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn revoked_tokens_are_rejected() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = app.create_api_token(&["newsletter:publish"]).await;
+    let token_id = sqlx::query!("SELECT token_id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .token_id;
+
+    let response = app
+        .api_client
+        .post(format!(
+            "{}/admin/api-tokens/{}/revoke",
+            &app.addr, token_id
+        ))
+        .form(&serde_json::json!({ "csrf_token": app.csrf_token() }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_is_redirect_to(&response, "/admin/api-tokens");
+    let html_page = app.get_html("/admin/api-tokens").await;
+    assert!(html_page.contains("The API token has been revoked."));
+
+    let draft_id = app.create_draft(&newsletter_request_body()).await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletter", &app.addr))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({ "draft_id": draft_id }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_token_needs_at_least_one_scope() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .post_api_tokens(&serde_json::json!({ "name": "CI" }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/api-tokens");
+    let html_page = app.get_html("/admin/api-tokens").await;
+    assert!(html_page.contains("Select at least one scope for the token."));
+}
+
+#[tokio::test]
+async fn subscribers_can_be_listed_with_the_read_scope() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.test_user.login(&app).await;
+    let read_token = app.create_api_token(&["subscribers:read"]).await;
+    let publish_token = app.create_api_token(&["newsletter:publish"]).await;
+
+    let list = |token: String| {
+        reqwest::Client::new()
+            .get(format!("{}/subscriptions", &app.addr))
+            .bearer_auth(token)
+            .send()
+    };
+    let response = list(publish_token).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = list(read_token).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let subscribers: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscribers[0]["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(subscribers[0]["status"], "pending_confirmation");
+}
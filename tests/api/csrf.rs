@@ -0,0 +1,164 @@
+use crate::helpers::{assert_is_redirect_to, extract_csrf_token, spawn_app};
+
+#[tokio::test]
+async fn forms_without_a_csrf_token_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/login", &app.addr))
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app
+        .api_client
+        .post(format!("{}/subscriptions", &app.addr))
+        .form(&serde_json::json!({ "name": "le guin", "email": "ursula_le_guin@gmail.com" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn a_cross_site_form_cannot_log_an_editor_out() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.addr))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_token_without_its_cookie_is_rejected() {
+    let app = spawn_app().await;
+
+    // A different browser, replaying the token of the test client.
+    let response = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .post(format!("{}/login", &app.addr))
+        .form(&app.with_csrf_token(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        })))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn the_token_can_be_sent_in_a_header() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/login", &app.addr))
+        .header("X-CSRF-Token", app.csrf_token())
+        .form(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn the_token_stays_the_same_across_pages() {
+    let app = spawn_app().await;
+
+    let html_page = app.get_html("/login/forgot-password").await;
+    assert_eq!(extract_csrf_token(&html_page), app.csrf_token());
+    let html_page = app.get_html("/").await;
+    assert_eq!(extract_csrf_token(&html_page), app.csrf_token());
+}
+
+#[tokio::test]
+async fn the_token_is_replaced_on_login_and_logout() {
+    let app = spawn_app().await;
+    let planted_token = app.csrf_token();
+
+    app.test_user.login(&app).await;
+    let logged_in_token = app.csrf_token();
+    assert_ne!(logged_in_token, planted_token);
+    let html_page = app.get_html("/admin/password").await;
+    assert_eq!(extract_csrf_token(&html_page), logged_in_token);
+
+    // The token from before the login no longer works
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.addr))
+        .form(&serde_json::json!({ "csrf_token": planted_token }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+    assert_ne!(app.csrf_token(), logged_in_token);
+}
+
+#[tokio::test]
+async fn api_requests_need_a_token_when_they_carry_a_session_cookie() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/logout", &app.addr))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&serde_json::json!({}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn only_drafts_may_post_forms_over_the_default_limit() {
+    let app = spawn_app().await;
+    let padding = "a".repeat(20 * 1024);
+
+    for path in ["/login", "/subscriptions", "/login/forgot-password"] {
+        let response = app
+            .api_client
+            .post(format!("{}{}", &app.addr, path))
+            .form(&app.with_csrf_token(&serde_json::json!({
+                "username": &app.test_user.username,
+                "password": &padding,
+            })))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 413);
+    }
+}
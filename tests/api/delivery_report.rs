@@ -0,0 +1,325 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{any, body_partial_json};
+use wiremock::{Mock, ResponseTemplate};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "markdown": "Newsletter body",
+        }
+    })
+}
+
+async fn post_resume(app: &TestApp, issue_id: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!(
+            "{}/admin/newsletters/{}/resume",
+            &app.addr, issue_id
+        ))
+        .form(&app.with_csrf_token(&serde_json::json!({})))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+/// Publish an issue and wait for it to be delivered, returning its id.
+async fn publish(app: &TestApp) -> String {
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    app.wait_for_pending_deliveries().await;
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_a_delivery_report() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/admin/newsletters/{}",
+            &app.addr,
+            Uuid::new_v4()
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn unknown_issues_have_no_delivery_report() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/admin/newsletters/{}",
+            &app.addr,
+            Uuid::new_v4()
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_delivery_report_counts_deliveries_and_lists_failures() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    app.create_confirmed_subscriber("invalid_recipient@gmail.com")
+        .await;
+    Mock::given(body_partial_json(
+        serde_json::json!({ "To": "invalid_recipient@gmail.com" }),
+    ))
+    .respond_with(ResponseTemplate::new(422))
+    .mount(&app.email_server)
+    .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issue_id = body["newsletter_issue_id"].as_str().unwrap().to_owned();
+    app.wait_for_pending_deliveries().await;
+    app.test_user.login(&app).await;
+
+    let html_page = app.get_html("/admin/newsletters").await;
+    assert!(html_page.contains("Newsletter title"));
+    assert!(html_page.contains(&format!("/admin/newsletters/{}", issue_id)));
+
+    let html_page = app
+        .get_html(&format!("/admin/newsletters/{}", issue_id))
+        .await;
+    assert!(html_page.contains("<li>queued: 0</li>"));
+    assert!(html_page.contains("<li>sent: 1</li>"));
+    assert!(html_page.contains("<li>failed: 0</li>"));
+    assert!(html_page.contains("<li>bounced: 1</li>"));
+    assert!(html_page.contains("<td>invalid_recipient@gmail.com</td><td>bounced</td>"));
+    assert!(!html_page.contains("<td>ursula_le_guin@gmail.com</td>"));
+}
+
+#[tokio::test]
+async fn failures_are_paginated() {
+    let app = spawn_app().await;
+    let issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, title, text_content, html_content, status, published_at,
+            updated_at
+        )
+        VALUES ($1, 'Newsletter title', 'text', '<p>html</p>', 'published', now(), now())
+        "#,
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (
+            newsletter_issue_id, subscriber_email, status, n_attempts, queued_at, updated_at
+        )
+        SELECT $1, 'subscriber' || n || '@example.com', 'failed', 6, now(), now()
+        FROM generate_series(1, 51) AS n
+        "#,
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+
+    let html_page = app
+        .get_html(&format!("/admin/newsletters/{}", issue_id))
+        .await;
+    assert!(html_page.contains("<li>failed: 51</li>"));
+    assert_eq!(html_page.matches("<td>failed</td>").count(), 50);
+    assert!(html_page.contains(&format!("/admin/newsletters/{}?page=2", issue_id)));
+    assert!(!html_page.contains("Previous"));
+
+    let html_page = app
+        .get_html(&format!("/admin/newsletters/{}?page=2", issue_id))
+        .await;
+    assert_eq!(html_page.matches("<td>failed</td>").count(), 1);
+    assert!(html_page.contains(&format!("/admin/newsletters/{}?page=1", issue_id)));
+    assert!(!html_page.contains("Next"));
+}
+
+#[tokio::test]
+async fn resuming_a_delivery_only_targets_recipients_who_did_not_get_the_issue() {
+    let app = spawn_app_with(|c| c.issue_delivery.max_retries = 0).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    app.create_confirmed_subscriber("flaky_inbox@gmail.com")
+        .await;
+    app.create_confirmed_subscriber("invalid_recipient@gmail.com")
+        .await;
+    Mock::given(body_partial_json(
+        serde_json::json!({ "To": "flaky_inbox@gmail.com" }),
+    ))
+    .respond_with(ResponseTemplate::new(503))
+    .up_to_n_times(1)
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+    Mock::given(body_partial_json(
+        serde_json::json!({ "To": "invalid_recipient@gmail.com" }),
+    ))
+    .respond_with(ResponseTemplate::new(422))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+    // Ursula's copy the first time, then the flaky inbox's on resume
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let issue_id = publish(&app).await;
+    app.test_user.login(&app).await;
+    let report_url = format!("/admin/newsletters/{}", issue_id);
+    let html_page = app.get_html(&report_url).await;
+    assert!(html_page.contains("<li>failed: 1</li>"));
+    assert!(html_page.contains(&format!("{}/resume", report_url)));
+
+    let response = post_resume(&app, &issue_id).await;
+    assert_is_redirect_to(&response, &report_url);
+    let html_page = app.get_html(&report_url).await;
+    assert!(html_page.contains("Resumed the delivery to 1 recipient(s)."));
+    app.wait_for_pending_deliveries().await;
+
+    let html_page = app.get_html(&report_url).await;
+    assert!(html_page.contains("<li>sent: 2</li>"));
+    assert!(html_page.contains("<li>failed: 0</li>"));
+    assert!(html_page.contains("<li>bounced: 1</li>"));
+    let delivery = sqlx::query!(
+        "SELECT n_attempts FROM issue_deliveries WHERE subscriber_email = 'flaky_inbox@gmail.com'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(delivery.n_attempts, 2);
+
+    // Nothing is left to resume: nobody gets a second copy
+    let response = post_resume(&app, &issue_id).await;
+    assert_is_redirect_to(&response, &report_url);
+    let html_page = app.get_html(&report_url).await;
+    assert!(html_page.contains("There are no failed deliveries to resume."));
+    app.wait_for_pending_deliveries().await;
+}
+
+#[tokio::test]
+async fn recipients_who_unsubscribed_are_skipped_rather_than_failed() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let issue_id = publish(&app).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed' WHERE email = 'ursula_le_guin@gmail.com'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    // Her copy was queued before she unsubscribed.
+    let mut transaction = app.db_pool.begin().await.unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+        VALUES ($1, 'ursula_le_guin@gmail.com')
+        "#,
+        Uuid::parse_str(&issue_id).unwrap()
+    )
+    .execute(&mut transaction)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (
+            newsletter_issue_id, subscriber_email, status, queued_at, updated_at
+        )
+        VALUES ($1, 'ursula_le_guin@gmail.com', 'queued', now(), now())
+        "#,
+        Uuid::parse_str(&issue_id).unwrap()
+    )
+    .execute(&mut transaction)
+    .await
+    .unwrap();
+    transaction.commit().await.unwrap();
+    app.wait_for_pending_deliveries().await;
+    app.test_user.login(&app).await;
+
+    let report_url = format!("/admin/newsletters/{}", issue_id);
+    let html_page = app.get_html(&report_url).await;
+    assert!(html_page.contains("<li>failed: 0</li>"));
+    assert!(html_page.contains("<li>skipped: 1</li>"));
+    assert!(!html_page.contains("<td>ursula_le_guin@gmail.com</td>"));
+    assert!(!html_page.contains(&format!("{}/resume", report_url)));
+
+    let response = post_resume(&app, &issue_id).await;
+    assert_is_redirect_to(&response, &report_url);
+    let html_page = app.get_html(&report_url).await;
+    assert!(html_page.contains("There are no failed deliveries to resume."));
+    let queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 0);
+}
+
+#[tokio::test]
+async fn only_publishers_can_resume_a_delivery() {
+    let app = spawn_app_with(|c| c.issue_delivery.max_retries = 0).await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let issue_id = publish(&app).await;
+    app.test_user.set_role(&app, "viewer").await;
+    app.test_user.login(&app).await;
+
+    let response = post_resume(&app, &issue_id).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    let pending = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(pending.count, 0);
+}
+
+#[tokio::test]
+async fn viewers_cannot_see_a_delivery_report() {
+    let app = spawn_app().await;
+    let issue_id = publish(&app).await;
+    app.test_user.set_role(&app, "viewer").await;
+    app.test_user.login(&app).await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/newsletters/{}", &app.addr, issue_id))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 403);
+}
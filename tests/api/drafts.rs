@@ -0,0 +1,220 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp, TestUser};
+use uuid::Uuid;
+use wiremock::matchers::{any, body_partial_json};
+use wiremock::{Mock, ResponseTemplate};
+
+fn draft_form() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "markdown_content": "Newsletter *body*, with [a link](https://example.com)",
+    })
+}
+
+async fn post_draft(app: &TestApp, path: &str, body: &serde_json::Value) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}{}", &app.addr, path))
+        .form(&app.with_csrf_token(body))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+/// Save a new draft through the drafts page, returning its id.
+async fn save_draft(app: &TestApp) -> String {
+    let response = post_draft(app, "/admin/drafts", &draft_form()).await;
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response.headers()["Location"].to_str().unwrap();
+    location.strip_prefix("/admin/drafts/").unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_drafts() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/drafts", &app.addr))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn authors_can_save_and_update_drafts() {
+    let app = spawn_app().await;
+    app.test_user.set_role(&app, "author").await;
+    app.test_user.login(&app).await;
+
+    let draft_id = save_draft(&app).await;
+    let html_page = app.get_html(&format!("/admin/drafts/{}", draft_id)).await;
+    assert!(html_page.contains("The draft has been saved."));
+    assert!(html_page.contains("Newsletter *body*, with [a link](https://example.com)</textarea>"));
+
+    let mut form = draft_form();
+    form["title"] = "Updated title".into();
+    let response = post_draft(&app, &format!("/admin/drafts/{}", draft_id), &form).await;
+    assert_is_redirect_to(&response, &format!("/admin/drafts/{}", draft_id));
+
+    let html_page = app.get_html("/admin/drafts").await;
+    assert!(html_page.contains("Updated title"));
+    assert!(html_page.contains(&app.test_user.username));
+}
+
+#[tokio::test]
+async fn drafts_need_a_valid_title_and_content() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    // Six bytes per character once URL-encoded, yet within the form limit
+    let too_long = "é".repeat(150 * 1024);
+    let test_cases = [
+        ("title", "  ", "The draft needs a title."),
+        (
+            "markdown_content",
+            "  ",
+            "The content of an issue cannot be empty.",
+        ),
+        (
+            "title",
+            "News for {{ nmae }}",
+            "&#x27;{{ nmae }}&#x27; is not a template variable",
+        ),
+        (
+            "markdown_content",
+            "Hi {{ name",
+            "A template variable is missing its closing &#x27;}}&#x27;.",
+        ),
+        (
+            "markdown_content",
+            "[Your profile]({{ name }})",
+            "&#x27;{{ name }}&#x27; cannot be used in a link address",
+        ),
+        (
+            "markdown_content",
+            too_long.as_str(),
+            "The content of an issue cannot be longer than 256 KiB.",
+        ),
+    ];
+
+    for (field, value, error) in test_cases {
+        let mut form = draft_form();
+        form[field] = value.into();
+        let response = post_draft(&app, "/admin/drafts", &form).await;
+        assert_is_redirect_to(&response, "/admin/drafts");
+        let html_page = app.get_html("/admin/drafts").await;
+        assert!(html_page.contains(error));
+    }
+    let n_drafts = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_drafts.count, 0);
+}
+
+#[tokio::test]
+async fn drafts_can_be_longer_than_the_default_form_limit() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let markdown = "A fairly long paragraph of the newsletter. ".repeat(1000);
+    assert!(markdown.len() > 16 * 1024);
+
+    let mut form = draft_form();
+    form["markdown_content"] = markdown.clone().into();
+    let response = post_draft(&app, "/admin/drafts", &form).await;
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response.headers()["Location"].to_str().unwrap();
+    assert!(location.starts_with("/admin/drafts/"));
+
+    form["markdown_content"] = format!("{}\n\nAnd a postscript.", markdown).into();
+    let response = post_draft(&app, location, &form).await;
+    assert_is_redirect_to(&response, location);
+    let saved = sqlx::query!("SELECT markdown_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved
+        .markdown_content
+        .unwrap()
+        .ends_with("And a postscript."));
+}
+
+#[tokio::test]
+async fn viewers_cannot_write_drafts() {
+    let app = spawn_app().await;
+    app.test_user.set_role(&app, "viewer").await;
+    app.test_user.login(&app).await;
+
+    let response = post_draft(&app, "/admin/drafts", &draft_form()).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn drafts_can_be_previewed() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let draft_id = save_draft(&app).await;
+
+    let html_page = app
+        .get_html(&format!("/admin/drafts/{}/preview", draft_id))
+        .await;
+
+    // The content is rendered in a sandboxed frame, not in the admin page itself
+    assert!(html_page.contains("<iframe sandbox srcdoc="));
+    assert!(!html_page.contains("<em>body</em>"));
+    assert!(html_page
+        .contains("<pre>Newsletter body, with a link [1]\n\n[1] https://example.com\n</pre>"));
+}
+
+#[tokio::test]
+async fn test_copies_only_go_to_the_selected_editors() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    let other_editor = TestUser::generate();
+    other_editor.store(&app.db_pool).await;
+    app.test_user.login(&app).await;
+    let draft_id = save_draft(&app).await;
+    Mock::given(body_partial_json(serde_json::json!({
+        "To": &other_editor.email,
+        "Subject": "[Test] Newsletter title",
+    })))
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let mut form = serde_json::json!({});
+    form[other_editor.user_id.to_string()] = "on".into();
+    let response = post_draft(&app, &format!("/admin/drafts/{}/test", draft_id), &form).await;
+
+    assert_is_redirect_to(&response, &format!("/admin/drafts/{}", draft_id));
+    let html_page = app.get_html(&format!("/admin/drafts/{}", draft_id)).await;
+    assert!(html_page.contains("Sent a test copy to 1 editor(s)."));
+}
+
+#[tokio::test]
+async fn published_drafts_can_no_longer_be_edited() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let draft_id = save_draft(&app).await;
+
+    let response = app
+        .post_newsletters_as(
+            &app.test_user,
+            &serde_json::json!({ "draft_id": &draft_id }),
+            &Uuid::new_v4().to_string(),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    let response = post_draft(&app, &format!("/admin/drafts/{}", draft_id), &draft_form()).await;
+    assert_eq!(response.status().as_u16(), 404);
+    app.wait_for_pending_deliveries().await;
+}
@@ -1,13 +1,20 @@
+use actix_web::cookie::{Cookie, CookieJar, Key};
 use argon2::password_hash::SaltString;
 use argon2::{Argon2, PasswordHasher};
+use fake::faker::internet::en::SafeEmail;
+use fake::Fake;
 use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, Secret};
 use sha3::Digest;
 
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::sync::Mutex;
 use uuid::Uuid;
 use wiremock::MockServer;
 
-use kobo::configuration::{get_configuration, DatabaseSettings};
+use kobo::cli::{run_admin_command, AdminCommand};
+use kobo::configuration::{get_configuration, DatabaseSettings, OidcSettings, Settings};
+use kobo::domain::NewsletterContent;
 
 use kobo::startup::{get_connection_pool, Application};
 use kobo::telemetry;
@@ -27,21 +34,80 @@ pub struct TestApp {
     pub addr: String,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    /// Plays the OpenID Connect identity provider.
+    pub idp_server: MockServer,
     pub port: u16,
     pub test_user: TestUser,
+    pub api_client: reqwest::Client,
+    /// Verifies the signed CSRF cookies handed to `api_client`.
+    csrf_key: Key,
+    /// The token `api_client` got in its CSRF cookie, replaced on login and logout.
+    csrf_token: Mutex<String>,
 }
 
 impl TestApp {
+    /// The CSRF token to send back with every form.
+    pub fn csrf_token(&self) -> String {
+        self.csrf_token.lock().unwrap().clone()
+    }
+
+    /// Pick up the new CSRF token if `response` replaced it.
+    pub fn track_csrf_token(&self, response: &reqwest::Response) {
+        let cookie = match response.cookies().find(|cookie| cookie.name() == "_csrf") {
+            Some(cookie) => cookie,
+            None => return,
+        };
+        let mut jar = CookieJar::new();
+        jar.add_original(Cookie::new("_csrf", cookie.value().to_string()));
+        let token = jar
+            .signed(&self.csrf_key)
+            .get("_csrf")
+            .expect("The CSRF cookie is not signed with our key");
+        *self.csrf_token.lock().unwrap() = token.value().to_string();
+    }
+
+    /// Add the CSRF token to a form body, as the hidden field of our pages would.
+    pub fn with_csrf_token<Body>(&self, body: &Body) -> serde_json::Value
+    where
+        Body: serde::Serialize,
+    {
+        let mut body = serde_json::to_value(body).unwrap();
+        body["csrf_token"] = self.csrf_token().into();
+        body
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
-        reqwest::Client::new()
+        self.api_client
+            .post(format!("{}/subscriptions", &self.addr))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(format!("{}&csrf_token={}", body, self.csrf_token()))
+            .send()
+            .await
+            .expect("Failed to exec request")
+    }
+
+    /// Submit the subscription form the way a browser would.
+    pub async fn post_subscriptions_form(&self, body: String) -> reqwest::Response {
+        self.api_client
             .post(format!("{}/subscriptions", &self.addr))
             .header("Content-Type", "application/x-www-form-urlencoded")
-            .body(body)
+            .header("Accept", "text/html")
+            .body(format!("{}&csrf_token={}", body, self.csrf_token()))
             .send()
             .await
             .expect("Failed to exec request")
     }
 
+    /// Submit the form of the page an unsubscribe link leads to.
+    pub async fn post_unsubscribe(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions/unsubscribe", &self.addr))
+            .form(&self.with_csrf_token(&serde_json::json!({ "token": token })))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
         let get_link = |s: &str| {
@@ -62,15 +128,293 @@ impl TestApp {
         ConfirmationLinks { html, plain_text }
     }
 
+    /// Save `body` as a draft, then publish it as the test user, with a fresh idempotency key.
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        let draft_id = self.create_draft(&body).await;
+        let mut publish_body = serde_json::json!({ "draft_id": draft_id });
+        if let Some(publish_at) = body.get("publish_at") {
+            publish_body["publish_at"] = publish_at.clone();
+        }
+        self.post_newsletters_as(&self.test_user, &publish_body, &Uuid::new_v4().to_string())
+            .await
+    }
+
+    /// Store a draft of the test user directly, skipping the drafts page.
+    pub async fn create_draft(&self, body: &serde_json::Value) -> String {
+        let draft_id = Uuid::new_v4();
+        let content = NewsletterContent::parse(body["content"]["markdown"].as_str().unwrap())
+            .expect("Invalid draft content");
+        sqlx::query!(
+            r#"
+            INSERT INTO newsletter_issues (
+                newsletter_issue_id, title, markdown_content, text_content, html_content, status,
+                created_by, updated_at
+            )
+            VALUES ($1, $2, $3, $4, $5, 'draft', $6, now())
+            "#,
+            draft_id,
+            body["title"].as_str().unwrap(),
+            content.markdown(),
+            content.text(),
+            content.html(),
+            self.test_user.user_id
+        )
+        .execute(&self.db_pool)
+        .await
+        .expect("Failed to store the draft");
+        draft_id.to_string()
+    }
+
+    pub async fn post_newsletters_as(
+        &self,
+        user: &TestUser,
+        body: &serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
         reqwest::Client::new()
             .post(format!("{}/newsletter", &self.addr))
+            .basic_auth(&user.username, Some(&user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_scheduled_issues(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/newsletter/scheduled", &self.addr))
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
-            .json(&body)
             .send()
             .await
             .expect("Failed to execute request")
     }
+
+    pub async fn put_scheduled_issue(
+        &self,
+        newsletter_issue_id: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!(
+                "{}/newsletter/scheduled/{}",
+                &self.addr, newsletter_issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn delete_scheduled_issue(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!(
+                "{}/newsletter/scheduled/{}",
+                &self.addr, newsletter_issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Store a confirmed subscriber directly, skipping the confirmation email.
+    pub async fn create_confirmed_subscriber(&self, email: &str) {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES ($1, $2, $2, now(), 'confirmed')
+            "#,
+            Uuid::new_v4(),
+            email
+        )
+        .execute(&self.db_pool)
+        .await
+        .expect("Failed to store a confirmed subscriber");
+    }
+
+    /// Wait for the background workers to drain the delivery queue.
+    pub async fn wait_for_pending_deliveries(&self) {
+        for _ in 0..200 {
+            let pending = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+                .fetch_one(&self.db_pool)
+                .await
+                .expect("Failed to count pending deliveries")
+                .count;
+            if pending == 0 {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        panic!("The delivery queue was not drained in time");
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        let response = self
+            .api_client
+            .post(format!("{}/login", &self.addr))
+            .form(&self.with_csrf_token(body))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        self.track_csrf_token(&response);
+        response
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.addr))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/password", &self.addr))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        let response = self
+            .api_client
+            .post(format!("{}/admin/password", &self.addr))
+            .form(&self.with_csrf_token(body))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        self.track_csrf_token(&response);
+        response
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        let response = self
+            .api_client
+            .post(format!("{}/admin/logout", &self.addr))
+            .form(&serde_json::json!({ "csrf_token": self.csrf_token() }))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        self.track_csrf_token(&response);
+        response
+    }
+
+    pub async fn post_forgot_password(&self, username: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/forgot-password", &self.addr))
+            .form(&serde_json::json!({ "username": username, "csrf_token": self.csrf_token() }))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_reset_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login/reset-password", &self.addr))
+            .form(&self.with_csrf_token(body))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_login_two_factor<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        let response = self
+            .api_client
+            .post(format!("{}/login/two-factor", &self.addr))
+            .form(&self.with_csrf_token(body))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        self.track_csrf_token(&response);
+        response
+    }
+
+    pub async fn post_enable_two_factor<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/two-factor/enable", &self.addr))
+            .form(&self.with_csrf_token(body))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_disable_two_factor<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/two-factor/disable", &self.addr))
+            .form(&self.with_csrf_token(body))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_api_tokens<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/api-tokens", &self.addr))
+            .form(&self.with_csrf_token(body))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Create an API token through the admin area, returning it in clear.
+    pub async fn create_api_token(&self, scopes: &[&str]) -> String {
+        let mut body = serde_json::json!({ "name": "CI" });
+        for scope in scopes {
+            body[scope] = "on".into();
+        }
+        let html_page = self.post_api_tokens(&body).await.text().await.unwrap();
+        html_page
+            .split(r#"<code id="api-token">"#)
+            .nth(1)
+            .and_then(|rest| rest.split("</code>").next())
+            .expect("No API token on the page")
+            .to_string()
+    }
+
+    /// Run a `kobo admin` subcommand against the test database.
+    pub async fn run_admin(&self, command: AdminCommand) -> Result<String, anyhow::Error> {
+        let policy = get_configuration()
+            .expect("Failed to read configuration")
+            .password_hashing
+            .policy()
+            .unwrap();
+        run_admin_command(command, &self.db_pool, &policy).await
+    }
+
+    pub async fn get_html(&self, path: &str) -> String {
+        self.api_client
+            .get(format!("{}{}", &self.addr, path))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
 }
 
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
@@ -96,11 +440,17 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the app after tweaking its configuration.
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     // Uncomment this if you want tracing logs.
     // Need to add a sink for debug
     // Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
+    let idp_server = MockServer::start().await;
 
     let configuration = {
         let mut c = get_configuration().expect("Failed to read configuration.");
@@ -110,6 +460,23 @@ pub async fn spawn_app() -> TestApp {
         c.application.port = 0;
         // Use the mock server as email API
         c.email_client.base_url = email_server.uri();
+        // Don't keep tests waiting for queued deliveries
+        c.issue_delivery.poll_interval_millis = 50;
+        c.issue_delivery.base_retry_delay_millis = 10;
+        c.issue_delivery.max_retry_delay_millis = 100;
+        c.issue_scheduling.poll_interval_millis = 50;
+        // Use the other mock server as identity provider
+        c.oidc = Some(OidcSettings {
+            provider_name: "Test IdP".into(),
+            issuer_url: idp_server.uri(),
+            client_id: "kobo".into(),
+            client_secret: Secret::new("kobo-secret".into()),
+            allow_email_linking: false,
+            allow_jit_provisioning: false,
+            jit_role: "viewer".into(),
+            discovery_ttl_minutes: 60,
+        });
+        customise(&mut c);
         c
     };
 
@@ -121,21 +488,61 @@ pub async fn spawn_app() -> TestApp {
     let application_port = application.port();
     let addr = format!("http://127.0.0.1:{}", application.port());
     let _ = tokio::spawn(application.run_until_stopped());
+    let api_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        // The server may close idle keep-alive connections just as a slow test reuses one
+        .pool_max_idle_per_host(0)
+        .build()
+        .unwrap();
+    let login_page = api_client
+        .get(format!("{}/login", &addr))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let csrf_token = Mutex::new(extract_csrf_token(&login_page));
     let app = TestApp {
         addr,
         db_pool: get_connection_pool(&configuration.database),
         email_server,
+        idp_server,
         port: application_port,
         test_user: TestUser::generate(),
+        api_client,
+        csrf_key: Key::from(
+            configuration
+                .application
+                .hmac_secret
+                .expose_secret()
+                .as_bytes(),
+        ),
+        csrf_token,
     };
     app.test_user.store(&app.db_pool).await;
     app
 }
 
+/// Find the value of the hidden CSRF field in a page.
+pub fn extract_csrf_token(html_page: &str) -> String {
+    let marker = "name=\"csrf_token\" value=\"";
+    let start = html_page.find(marker).expect("No CSRF token in the page") + marker.len();
+    let end = start + html_page[start..].find('"').unwrap();
+    html_page[start..end].to_string()
+}
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
+}
+
 pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
     pub password: String,
+    pub email: String,
 }
 
 impl TestUser {
@@ -144,20 +551,57 @@ impl TestUser {
             user_id: Uuid::new_v4(),
             username: Uuid::new_v4().to_string(),
             password: Uuid::new_v4().to_string(),
+            email: SafeEmail().fake(),
         }
     }
 
-    async fn store(&self, pool: &PgPool) {
+    pub async fn login(&self, app: &TestApp) {
+        let response = app
+            .post_login(&serde_json::json!({
+                "username": &self.username,
+                "password": &self.password
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/admin/dashboard");
+    }
+
+    pub async fn set_role(&self, app: &TestApp, role: &str) {
+        sqlx::query!(
+            "UPDATE editors SET role = $1 WHERE user_id = $2",
+            role,
+            self.user_id
+        )
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to change the test user's role");
+    }
+
+    /// Go through the password step only, for editors with two-factor authentication.
+    pub async fn login_with_password(&self, app: &TestApp) {
+        let response = app
+            .post_login(&serde_json::json!({
+                "username": &self.username,
+                "password": &self.password
+            }))
+            .await;
+        assert_is_redirect_to(&response, "/login/two-factor");
+    }
+
+    pub async fn store(&self, pool: &PgPool) {
         let salt = SaltString::generate(&mut rand::thread_rng());
         let password_hash = Argon2::default()
             .hash_password(self.password.as_bytes(), &salt)
             .unwrap()
             .to_string();
         sqlx::query!(
-            r#"INSERT INTO editors (user_id, username, password_hash) VALUES ($1, $2, $3)"#,
+            r#"
+            INSERT INTO editors (user_id, username, password_hash, role, email)
+            VALUES ($1, $2, $3, 'owner', $4)
+            "#,
             self.user_id,
             self.username,
-            password_hash
+            password_hash,
+            self.email
         )
         .execute(pool)
         .await
@@ -0,0 +1,194 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+use kobo::configuration::ClientIpSource;
+
+#[tokio::test]
+async fn an_error_message_is_shown_on_failure() {
+    let app = spawn_app().await;
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_html("/login").await;
+    assert!(html_page.contains(r#"<p class="flash-error"><i>Authentication failed</i></p>"#));
+
+    // Flash messages are one-shot: reloading the page clears them
+    let html_page = app.get_html("/login").await;
+    assert!(!html_page.contains("Authentication failed"));
+}
+
+#[tokio::test]
+async fn tampered_flash_messages_are_ignored() {
+    let app = spawn_app().await;
+    let forged = base64::encode(r#"[{"level":"error","content":"Forged message"}]"#);
+
+    let html_page = reqwest::Client::new()
+        .get(format!("{}/login", &app.addr))
+        .header("Cookie", format!("_flash={}", forged))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .text()
+        .await
+        .unwrap();
+    assert!(!html_page.contains("Forged message"));
+}
+
+#[tokio::test]
+async fn a_wrong_password_is_rejected() {
+    let app = spawn_app().await;
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "definitely-not-the-password"
+    });
+
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    let app = spawn_app().await;
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    assert!(response.cookies().any(|c| c.name() == "id"));
+}
+
+#[tokio::test]
+async fn outdated_password_hashes_are_upgraded_on_login() {
+    let app = spawn_app().await;
+    let stored_hash = || async {
+        sqlx::query!(
+            "SELECT password_hash FROM editors WHERE user_id = $1",
+            app.test_user.user_id
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .password_hash
+    };
+    // The test user is stored with argon2's default, weaker parameters
+    assert!(stored_hash().await.contains("m=4096,t=3,p=1"));
+
+    app.test_user.login(&app).await;
+    let upgraded_hash = stored_hash().await;
+    assert!(upgraded_hash.starts_with("$argon2id$v=19$m=15000,t=2,p=1$"));
+
+    // The password still works, and an up-to-date hash is left untouched
+    app.post_logout().await;
+    app.test_user.login(&app).await;
+    assert_eq!(stored_hash().await, upgraded_hash);
+}
+
+#[tokio::test]
+async fn repeated_failures_lock_the_editor_out() {
+    let app = spawn_app().await;
+    let wrong_login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "definitely-not-the-password"
+    });
+    for _ in 0..5 {
+        let response = app.post_login(&wrong_login_body).await;
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Too many failed login attempts"));
+}
+
+#[tokio::test]
+async fn a_successful_login_resets_the_failure_count() {
+    let app = spawn_app().await;
+    let wrong_login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "definitely-not-the-password"
+    });
+    for _ in 0..4 {
+        app.post_login(&wrong_login_body).await;
+    }
+    app.test_user.login(&app).await;
+    app.post_logout().await;
+
+    for _ in 0..4 {
+        app.post_login(&wrong_login_body).await;
+    }
+    app.test_user.login(&app).await;
+}
+
+#[tokio::test]
+async fn concurrent_failures_cannot_get_past_the_lockout() {
+    let app = spawn_app().await;
+    let wrong_login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "definitely-not-the-password"
+    });
+
+    let responses =
+        futures::future::join_all((0..10).map(|_| app.post_login(&wrong_login_body))).await;
+    // Only the attempts under the threshold get to check the password
+    let checked = responses
+        .iter()
+        .filter(|response| response.status().as_u16() != 429)
+        .count();
+    assert_eq!(checked, 5);
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+async fn post_login_from(app: &TestApp, client_ip: &str, body: &serde_json::Value) -> u16 {
+    app.api_client
+        .post(format!("{}/login", &app.addr))
+        .header("X-Forwarded-For", client_ip)
+        .form(&app.with_csrf_token(body))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .status()
+        .as_u16()
+}
+
+#[tokio::test]
+async fn failures_are_counted_against_the_forwarded_client_ip_when_configured() {
+    let app = spawn_app_with(|c| {
+        c.login_throttling.max_failed_attempts_per_client_ip = 2;
+        c.login_throttling.client_ip_source = ClientIpSource::ForwardedHeader;
+    })
+    .await;
+    for username in ["someone", "someone-else"] {
+        let body = serde_json::json!({"username": username, "password": "a-guess"});
+        assert_eq!(post_login_from(&app, "203.0.113.1", &body).await, 303);
+    }
+
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+    assert_eq!(post_login_from(&app, "203.0.113.1", &login_body).await, 429);
+    // Other clients behind the same proxy are not locked out
+    assert_eq!(post_login_from(&app, "203.0.113.2", &login_body).await, 303);
+}
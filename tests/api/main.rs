@@ -1,5 +1,20 @@
+mod admin_cli;
+mod admin_dashboard;
+mod api_tokens;
+mod change_password;
+mod csrf;
+mod delivery_report;
+mod drafts;
 mod health_check;
 mod helpers;
+mod login;
 mod newsletter;
+mod oidc;
+mod password_reset;
+mod roles;
+mod scheduled_issues;
+mod sessions;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod two_factor;
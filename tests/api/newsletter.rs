@@ -1,6 +1,7 @@
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{spawn_app, spawn_app_with, ConfirmationLinks, TestApp, TestUser};
+use std::time::Duration;
 use uuid::Uuid;
-use wiremock::matchers::{any, method, path};
+use wiremock::matchers::{any, body_partial_json, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
 #[tokio::test]
@@ -17,13 +18,13 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     let newsletter_request_body = serde_json::json!({
           "title": "Newsletter title",
           "content": {
-            "text": "Newsletter body as plain text",
-            "html": "<p>Newsletter body</p>",
+            "markdown": "Newsletter body",
         }
     });
 
     let response = app.post_newsletters(newsletter_request_body).await;
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
+    app.wait_for_pending_deliveries().await;
 }
 
 #[tokio::test]
@@ -41,38 +42,30 @@ async fn newsletter_is_delivered_to_confirmed_subscribers() {
     let newsletter_request_body = serde_json::json!({
           "title": "Newsletter title",
           "content": {
-            "text": "Newsletter body as plain text",
-            "html": "<p>Newsletter body</p>",
+            "markdown": "Newsletter body",
         }
     });
 
     let response = app.post_newsletters(newsletter_request_body).await;
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
+    app.wait_for_pending_deliveries().await;
 }
 
 #[tokio::test]
 async fn newsletter_returns_400_for_invalid_data() {
     let app = spawn_app().await;
     let test_cases = vec![
+        (serde_json::json!({}), "missing draft id"),
         (
-            serde_json::json!({
-                "content": {
-                    "text": "Newsletter body",
-                    "html": "<p>Newsletter body</p>",
-                }
-            }),
-            "missing title",
-        ),
-        (
-            serde_json::json!({
-                "title": "newsletter!"
-            }),
-            "missing content",
+            serde_json::json!({ "draft_id": "newsletter!" }),
+            "invalid draft id",
         ),
     ];
 
     for (invalid_body, err_msg) in test_cases {
-        let response = app.post_newsletters(invalid_body).await;
+        let response = app
+            .post_newsletters_as(&app.test_user, &invalid_body, &Uuid::new_v4().to_string())
+            .await;
         assert_eq!(
             response.status().as_u16(),
             400,
@@ -82,16 +75,34 @@ async fn newsletter_returns_400_for_invalid_data() {
     }
 }
 
+#[tokio::test]
+async fn only_saved_drafts_can_be_published() {
+    let app = spawn_app().await;
+    let unknown_draft = serde_json::json!({ "draft_id": Uuid::new_v4() });
+    let response = app
+        .post_newsletters_as(&app.test_user, &unknown_draft, &Uuid::new_v4().to_string())
+        .await;
+    assert_eq!(404, response.status().as_u16());
+
+    let body = publish_request_body(&app).await;
+    let response = app
+        .post_newsletters_as(&app.test_user, &body, &Uuid::new_v4().to_string())
+        .await;
+    assert_eq!(202, response.status().as_u16());
+
+    // Publishing it again, with another idempotency key, is refused
+    let response = app
+        .post_newsletters_as(&app.test_user, &body, &Uuid::new_v4().to_string())
+        .await;
+    assert_eq!(409, response.status().as_u16());
+    app.wait_for_pending_deliveries().await;
+}
+
 #[tokio::test]
 async fn requests_missing_authorization_are_rejected() {
     let app = spawn_app().await;
-    let newsletter_request_body = serde_json::json!({
-          "title": "Newsletter title",
-          "content": {
-            "text": "Newsletter body as plain text",
-            "html": "<p>Newsletter body</p>",
-        }
-    });
+    // Authentication is checked before the draft is looked up
+    let newsletter_request_body = serde_json::json!({ "draft_id": Uuid::new_v4() });
     let response = reqwest::Client::new()
         .post(format!("{}/newsletter", &app.addr))
         .json(&newsletter_request_body)
@@ -111,13 +122,8 @@ async fn non_existing_user_is_rejected() {
     let app = spawn_app().await;
     let username = Uuid::new_v4().to_string();
     let password = Uuid::new_v4().to_string();
-    let newsletter_request_body = serde_json::json!({
-          "title": "Newsletter title",
-          "content": {
-            "text": "Newsletter body as plain text",
-            "html": "<p>Newsletter body</p>",
-        }
-    });
+    // Authentication is checked before the draft is looked up
+    let newsletter_request_body = serde_json::json!({ "draft_id": Uuid::new_v4() });
 
     let response = reqwest::Client::new()
         .post(format!("{}/newsletter", &app.addr))
@@ -133,6 +139,639 @@ async fn non_existing_user_is_rejected() {
     );
 }
 
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    let app = spawn_app().await;
+    let username = &app.test_user.username;
+    let password = Uuid::new_v4().to_string();
+    assert_ne!(app.test_user.password, password);
+    // Authentication is checked before the draft is looked up
+    let newsletter_request_body = serde_json::json!({ "draft_id": Uuid::new_v4() });
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletter", &app.addr))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn repeated_failures_lock_the_editor_out() {
+    let app = spawn_app().await;
+    // Authentication is checked before the draft is looked up
+    let newsletter_request_body = serde_json::json!({ "draft_id": Uuid::new_v4() });
+    let publish = |password: String| {
+        reqwest::Client::new()
+            .post(format!("{}/newsletter", &app.addr))
+            .basic_auth(&app.test_user.username, Some(password))
+            .json(&newsletter_request_body)
+            .send()
+    };
+
+    for _ in 0..5 {
+        let response = publish(Uuid::new_v4().to_string()).await.unwrap();
+        assert_eq!(401, response.status().as_u16());
+    }
+
+    // Even the right password is refused until the lockout is over
+    let response = publish(app.test_user.password.clone()).await.unwrap();
+    assert_eq!(429, response.status().as_u16());
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=30).contains(&retry_after));
+}
+
+#[tokio::test]
+async fn newsletter_issues_are_delivered_in_the_background() {
+    let app = spawn_app().await;
+    create_confirmed_subs(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(1)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(202, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+
+    let issue =
+        sqlx::query!("SELECT newsletter_issue_id, title, published_by FROM newsletter_issues")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(issue.title, "Newsletter title");
+    assert_eq!(issue.published_by, Some(app.test_user.user_id));
+    assert_eq!(
+        body["newsletter_issue_id"],
+        issue.newsletter_issue_id.to_string()
+    );
+    app.wait_for_pending_deliveries().await;
+}
+
+#[tokio::test]
+async fn issues_are_personalized_for_each_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subs(&app).await;
+    Mock::given(body_partial_json(
+        serde_json::json!({ "Subject": "News for john doe" }),
+    ))
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "News for {{ name }}",
+            "content": {
+                "markdown": "Hi {{ name }}, this was sent to {{ email }}.\n\n\
+                    [Unsubscribe]({{ unsubscribe_url }})",
+            }
+        }))
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    app.wait_for_pending_deliveries().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Hi john doe, this was sent to john_doe@gmail.com."));
+    let links = app.get_confirmation_links(&email_request);
+    assert_eq!(links.html, links.plain_text);
+    assert_eq!(links.html.path(), "/subscriptions/unsubscribe");
+    let (_, token) = links.html.query_pairs().next().unwrap();
+    let response = app.post_unsubscribe(&token).await;
+    assert_eq!(200, response.status().as_u16());
+    let subscriber = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn subscriber_names_cannot_turn_into_link_addresses() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'mallory@gmail.com', 'javascript:alert(document.cookie)', now(), 'confirmed')
+        "#,
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.test_user.login(&app).await;
+
+    // A draft putting the name in a link address can't be saved
+    let response = app
+        .api_client
+        .post(format!("{}/admin/drafts", &app.addr))
+        .form(&app.with_csrf_token(&serde_json::json!({
+            "title": "Newsletter title",
+            "markdown_content": "[Your profile]({{ name }})",
+        })))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 303);
+    let html_page = app.get_html("/admin/drafts").await;
+    assert!(html_page.contains("cannot be used in a link address"));
+
+    // As the text of a link, it stays text
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "markdown": "[Not {{ name }}?]({{ unsubscribe_url }})",
+            }
+        }))
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    app.wait_for_pending_deliveries().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    assert!(html_body.contains(">Not javascript:alert(document.cookie)?</a>"));
+    assert!(!html_body.contains("href=\"javascript"));
+}
+
+#[tokio::test]
+async fn drafts_with_unknown_template_variables_are_not_published() {
+    let app = spawn_app().await;
+    create_confirmed_subs(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let body = publish_request_body(&app).await;
+    // As saved before templates were validated
+    sqlx::query!("UPDATE newsletter_issues SET text_content = 'Hi {{ nmae }}'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_newsletters_as(&app.test_user, &body, &Uuid::new_v4().to_string())
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .starts_with("'{{ nmae }}' is not a template variable"));
+    let issue = sqlx::query!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.status, "draft");
+}
+
+#[tokio::test]
+async fn transient_delivery_failures_are_retried() {
+    let app = spawn_app().await;
+    create_confirmed_subs(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(2)
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(202, response.status().as_u16());
+    app.wait_for_pending_deliveries().await;
+
+    let delivery = sqlx::query!("SELECT status, n_attempts FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "sent");
+    assert_eq!(delivery.n_attempts, 3);
+}
+
+#[tokio::test]
+async fn permanent_delivery_failures_are_recorded_and_not_retried() {
+    let app = spawn_app().await;
+    create_confirmed_subs(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(202, response.status().as_u16());
+    app.wait_for_pending_deliveries().await;
+
+    let delivery =
+        sqlx::query!("SELECT subscriber_email, status, n_attempts FROM issue_deliveries")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(delivery.subscriber_email, "john_doe@gmail.com");
+    assert_eq!(delivery.status, "bounced");
+    assert_eq!(delivery.n_attempts, 1);
+}
+
+#[tokio::test]
+async fn deliveries_are_abandoned_once_retries_are_exhausted() {
+    let app = spawn_app_with(|c| c.issue_delivery.max_retries = 2).await;
+    create_confirmed_subs(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(503))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(202, response.status().as_u16());
+    app.wait_for_pending_deliveries().await;
+
+    let delivery = sqlx::query!("SELECT status, n_attempts FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "failed");
+    assert_eq!(delivery.n_attempts, 3);
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "markdown": "Newsletter body",
+        }
+    })
+}
+
+#[tokio::test]
+async fn large_audiences_are_delivered_in_batches() {
+    let app = spawn_app_with(|c| {
+        c.issue_delivery.workers = 2;
+        c.issue_delivery.batch_size = 4;
+    })
+    .await;
+    for i in 0..25 {
+        app.create_confirmed_subscriber(&format!("subscriber{:02}@gmail.com", i))
+            .await;
+    }
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(25)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(202, response.status().as_u16());
+    app.wait_for_pending_deliveries().await;
+
+    let sent = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM issue_deliveries WHERE status = 'sent' AND n_attempts = 1"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(sent.count, 25);
+}
+
+#[tokio::test]
+async fn emails_of_a_batch_are_sent_concurrently() {
+    let app = spawn_app_with(|c| {
+        c.issue_delivery.workers = 1;
+        c.issue_delivery.batch_size = 10;
+        c.issue_delivery.send_concurrency = 10;
+    })
+    .await;
+    for i in 0..10 {
+        app.create_confirmed_subscriber(&format!("subscriber{}@gmail.com", i))
+            .await;
+    }
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(1)))
+        .expect(10)
+        .mount(&app.email_server)
+        .await;
+
+    let started_at = std::time::Instant::now();
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(202, response.status().as_u16());
+    app.wait_for_pending_deliveries().await;
+
+    // Sending one at a time would take 10 seconds.
+    assert!(started_at.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn a_failure_midway_through_a_batch_does_not_resend_its_delivered_emails() {
+    let app = spawn_app_with(|c| {
+        c.issue_delivery.workers = 1;
+        c.issue_delivery.batch_size = 10;
+        c.issue_delivery.send_concurrency = 1;
+        c.issue_delivery.claim_ttl_seconds = 1;
+    })
+    .await;
+    for i in 0..5 {
+        app.create_confirmed_subscriber(&format!("subscriber{}@gmail.com", i))
+            .await;
+    }
+    // The third recipient's first attempt fails, and so does recording it.
+    Mock::given(body_partial_json(
+        serde_json::json!({ "To": "subscriber2@gmail.com" }),
+    ))
+    .respond_with(ResponseTemplate::new(500))
+    .up_to_n_times(1)
+    .mount(&app.email_server)
+    .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    sqlx::query(
+        r#"
+        CREATE FUNCTION fail_retries() RETURNS trigger AS $$
+        BEGIN
+            IF NEW.status = 'queued' THEN
+                RAISE EXCEPTION 'Cannot record a retry';
+            END IF;
+            RETURN NEW;
+        END;
+        $$ LANGUAGE plpgsql
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        r#"
+        CREATE TRIGGER fail_retries BEFORE UPDATE ON issue_deliveries
+        FOR EACH ROW EXECUTE FUNCTION fail_retries()
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(202, response.status().as_u16());
+    app.wait_for_pending_deliveries().await;
+
+    // Only the email whose outcome was lost is sent again, once its claim expires.
+    let mut recipients = recipients(&app).await;
+    recipients.sort();
+    assert_eq!(
+        recipients,
+        [
+            "subscriber0@gmail.com",
+            "subscriber1@gmail.com",
+            "subscriber2@gmail.com",
+            "subscriber2@gmail.com",
+            "subscriber3@gmail.com",
+            "subscriber4@gmail.com",
+        ]
+    );
+    let sent =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_deliveries WHERE status = 'sent'"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(sent.count, 5);
+}
+
+#[tokio::test]
+async fn subscribers_who_unsubscribe_after_publication_do_not_get_the_issue() {
+    let app = spawn_app_with(|c| {
+        c.issue_delivery.workers = 1;
+        c.issue_delivery.batch_size = 1;
+    })
+    .await;
+    app.create_confirmed_subscriber("subscriber0@gmail.com")
+        .await;
+    app.create_confirmed_subscriber("subscriber1@gmail.com")
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(1)))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(202, response.status().as_u16());
+    // The second subscriber opts out while the first email is on its way.
+    while recipients(&app).await.is_empty() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed' WHERE email = 'subscriber1@gmail.com'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.wait_for_pending_deliveries().await;
+
+    assert_eq!(recipients(&app).await, ["subscriber0@gmail.com"]);
+    let delivery = sqlx::query!(
+        "SELECT status FROM issue_deliveries WHERE subscriber_email = 'subscriber1@gmail.com'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(delivery.status, "skipped");
+}
+
+#[tokio::test]
+async fn requests_without_an_idempotency_key_are_rejected() {
+    let app = spawn_app().await;
+    let body = publish_request_body(&app).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletter", &app.addr))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(400, response.status().as_u16());
+
+    let response = app.post_newsletters_as(&app.test_user, &body, "").await;
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    let app = spawn_app().await;
+    create_confirmed_subs(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    let body = publish_request_body(&app).await;
+
+    let response = app
+        .post_newsletters_as(&app.test_user, &body, &idempotency_key)
+        .await;
+    assert_eq!(202, response.status().as_u16());
+
+    // The retry gets the same answer, without sending the issue again
+    let response = app
+        .post_newsletters_as(&app.test_user, &body, &idempotency_key)
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    app.wait_for_pending_deliveries().await;
+}
+
+#[tokio::test]
+async fn reusing_an_idempotency_key_for_another_request_is_rejected() {
+    let app = spawn_app().await;
+    create_confirmed_subs(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    let body = publish_request_body(&app).await;
+    let response = app
+        .post_newsletters_as(&app.test_user, &body, &idempotency_key)
+        .await;
+    assert_eq!(202, response.status().as_u16());
+
+    let other_body = publish_request_body(&app).await;
+    let response = app
+        .post_newsletters_as(&app.test_user, &other_body, &idempotency_key)
+        .await;
+
+    assert_eq!(422, response.status().as_u16());
+    assert_eq!(
+        response.text().await.unwrap(),
+        "The 'Idempotency-Key' was already used for a different request."
+    );
+    let draft = sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        Uuid::parse_str(other_body["draft_id"].as_str().unwrap()).unwrap()
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(draft.status, "draft");
+    app.wait_for_pending_deliveries().await;
+}
+
+#[tokio::test]
+async fn concurrent_duplicate_requests_are_handled_gracefully() {
+    let app = spawn_app().await;
+    create_confirmed_subs(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    let body = publish_request_body(&app).await;
+
+    let response1 = app.post_newsletters_as(&app.test_user, &body, &idempotency_key);
+    let response2 = app.post_newsletters_as(&app.test_user, &body, &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+    app.wait_for_pending_deliveries().await;
+}
+
+#[tokio::test]
+async fn idempotency_keys_are_scoped_to_their_editor() {
+    let app = spawn_app().await;
+    create_confirmed_subs(&app).await;
+    let other_editor = TestUser::generate();
+    other_editor.store(&app.db_pool).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    for editor in [&app.test_user, &other_editor] {
+        let body = publish_request_body(&app).await;
+        let response = app
+            .post_newsletters_as(editor, &body, &idempotency_key)
+            .await;
+        assert_eq!(202, response.status().as_u16());
+    }
+    app.wait_for_pending_deliveries().await;
+}
+
+#[tokio::test]
+async fn expired_idempotency_keys_can_be_reused() {
+    let app = spawn_app().await;
+    create_confirmed_subs(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    let body = publish_request_body(&app).await;
+    let response = app
+        .post_newsletters_as(&app.test_user, &body, &idempotency_key)
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    sqlx::query!("UPDATE idempotency SET created_at = now() - interval '25 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let body = publish_request_body(&app).await;
+    let response = app
+        .post_newsletters_as(&app.test_user, &body, &idempotency_key)
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    app.wait_for_pending_deliveries().await;
+}
+
+/// A request publishing a freshly saved draft.
+async fn publish_request_body(app: &TestApp) -> serde_json::Value {
+    let draft_id = app.create_draft(&newsletter_request_body()).await;
+    serde_json::json!({ "draft_id": draft_id })
+}
+
 async fn create_unconfirmed_subscribers(app: &TestApp) -> ConfirmationLinks {
     let body = "name=john%20doe&email=john_doe%40gmail.com";
     let _mock_guard = Mock::given(any())
@@ -165,3 +804,17 @@ async fn create_confirmed_subs(app: &TestApp) {
         .error_for_status()
         .expect("Unable to send confirmation request");
 }
+
+/// The recipients of the emails the mock email server received, in order.
+async fn recipients(app: &TestApp) -> Vec<String> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            body["To"].as_str().unwrap().to_string()
+        })
+        .collect()
+}
@@ -0,0 +1,363 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with, TestApp};
+use crate::two_factor::enable_two_factor;
+use chrono::{Duration, Utc};
+use kobo::configuration::OidcSettings;
+use openidconnect::core::{
+    CoreGenderClaim, CoreIdToken, CoreIdTokenClaims, CoreJsonWebKeySet, CoreJwsSigningAlgorithm,
+    CoreRsaPrivateSigningKey,
+};
+use openidconnect::{
+    Audience, EmptyAdditionalClaims, EndUserEmail, EndUserUsername, IssuerUrl, JsonWebKeyId, Nonce,
+    PkceCodeChallenge, PkceCodeVerifier, PrivateSigningKey, StandardClaims, SubjectIdentifier,
+};
+use std::collections::HashMap;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const SIGNING_KEY: &str = include_str!("oidc_signing_key.pem");
+
+fn signing_key() -> CoreRsaPrivateSigningKey {
+    CoreRsaPrivateSigningKey::from_pem(SIGNING_KEY, Some(JsonWebKeyId::new("test-key".into())))
+        .unwrap()
+}
+
+/// Serve the discovery document and the signing keys of the fake identity provider.
+async fn mount_discovery(app: &TestApp) {
+    let issuer = app.idp_server.uri();
+    Mock::given(method("GET"))
+        .and(path("/.well-known/openid-configuration"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{}/authorize", issuer),
+            "token_endpoint": format!("{}/token", issuer),
+            "jwks_uri": format!("{}/jwks", issuer),
+            "response_types_supported": ["code"],
+            "subject_types_supported": ["public"],
+            "id_token_signing_alg_values_supported": ["RS256"],
+        })))
+        .mount(&app.idp_server)
+        .await;
+    let jwks = CoreJsonWebKeySet::new(vec![signing_key().as_verification_key()]);
+    Mock::given(method("GET"))
+        .and(path("/jwks"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(jwks))
+        .mount(&app.idp_server)
+        .await;
+}
+
+/// The query of the authorization request the app redirected the editor to.
+async fn start_sign_in(app: &TestApp) -> HashMap<String, String> {
+    let response = app
+        .api_client
+        .get(format!("{}/login/oidc", &app.addr))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    let url = reqwest::Url::parse(location).unwrap();
+    assert!(url.as_str().starts_with(&app.idp_server.uri()));
+    url.query_pairs().into_owned().collect()
+}
+
+fn id_token(app: &TestApp, nonce: &str, claims: StandardClaims<CoreGenderClaim>) -> String {
+    let claims = CoreIdTokenClaims::new(
+        IssuerUrl::new(app.idp_server.uri()).unwrap(),
+        vec![Audience::new("kobo".into())],
+        Utc::now() + Duration::minutes(5),
+        Utc::now(),
+        claims,
+        EmptyAdditionalClaims {},
+    )
+    .set_nonce(Some(Nonce::new(nonce.into())));
+    let token = CoreIdToken::new(
+        claims,
+        &signing_key(),
+        CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256,
+        None,
+        None,
+    )
+    .unwrap();
+    serde_json::to_value(&token)
+        .unwrap()
+        .as_str()
+        .unwrap()
+        .to_string()
+}
+
+/// Go through the whole flow, with the identity provider vouching for `claims`.
+async fn sign_in(app: &TestApp, claims: StandardClaims<CoreGenderClaim>) -> reqwest::Response {
+    let request = start_sign_in(app).await;
+    Mock::given(method("POST"))
+        .and(path("/token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "access_token": "access-token",
+            "token_type": "bearer",
+            "expires_in": 3600,
+            "id_token": id_token(app, &request["nonce"], claims),
+        })))
+        .up_to_n_times(1)
+        .mount(&app.idp_server)
+        .await;
+    callback(app, &request["state"]).await
+}
+
+async fn callback(app: &TestApp, state: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/login/oidc/callback", &app.addr))
+        .query(&[("code", "authorization-code"), ("state", state)])
+        .send()
+        .await
+        .unwrap()
+}
+
+/// An app trusting the provider with the email addresses of existing editors.
+async fn spawn_app_linking_emails() -> TestApp {
+    spawn_app_with(|c| {
+        c.oidc = c.oidc.take().map(|oidc| OidcSettings {
+            allow_email_linking: true,
+            ..oidc
+        })
+    })
+    .await
+}
+
+fn claims(subject: &str) -> StandardClaims<CoreGenderClaim> {
+    StandardClaims::new(SubjectIdentifier::new(subject.into()))
+}
+
+fn verified_email(subject: &str, email: &str) -> StandardClaims<CoreGenderClaim> {
+    claims(subject)
+        .set_email(Some(EndUserEmail::new(email.into())))
+        .set_email_verified(Some(true))
+}
+
+#[tokio::test]
+async fn the_login_page_links_to_the_identity_provider() {
+    let app = spawn_app().await;
+
+    let html_page = app.get_html("/login").await;
+
+    assert!(html_page.contains(r#"<a href="/login/oidc">Sign in with Test IdP</a>"#));
+}
+
+#[tokio::test]
+async fn the_authorization_request_uses_pkce_and_a_nonce() {
+    let app = spawn_app().await;
+    mount_discovery(&app).await;
+
+    let request = start_sign_in(&app).await;
+
+    assert_eq!(request["response_type"], "code");
+    assert_eq!(request["client_id"], "kobo");
+    assert_eq!(request["code_challenge_method"], "S256");
+    assert!(request["scope"].split(' ').any(|s| s == "openid"));
+    assert!(!request["nonce"].is_empty());
+    assert_eq!(
+        request["redirect_uri"],
+        "http://127.0.0.1/login/oidc/callback"
+    );
+}
+
+#[tokio::test]
+async fn an_identity_with_the_email_of_an_editor_logs_in_as_that_editor() {
+    let app = spawn_app_linking_emails().await;
+    mount_discovery(&app).await;
+
+    let response = sign_in(&app, verified_email("subject-1", &app.test_user.email)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_html("/admin/dashboard").await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+
+    // The identity is now linked, whatever its email becomes.
+    app.post_logout().await;
+    let response = sign_in(&app, claims("subject-1")).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn the_provider_is_discovered_once_for_several_sign_ins() {
+    let app = spawn_app_linking_emails().await;
+    mount_discovery(&app).await;
+
+    let response = sign_in(&app, verified_email("subject-1", &app.test_user.email)).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+    let response = sign_in(&app, claims("subject-1")).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let discoveries = app
+        .idp_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|r| r.url.path() == "/.well-known/openid-configuration")
+        .count();
+    assert_eq!(discoveries, 1);
+}
+
+#[tokio::test]
+async fn emails_are_not_linked_unless_enabled() {
+    let app = spawn_app().await;
+    mount_discovery(&app).await;
+
+    let response = sign_in(&app, verified_email("subject-1", &app.test_user.email)).await;
+
+    assert_is_redirect_to(&response, "/login");
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn linked_editors_still_need_their_second_factor() {
+    let app = spawn_app_linking_emails().await;
+    mount_discovery(&app).await;
+    enable_two_factor(&app).await;
+    app.post_logout().await;
+
+    let response = sign_in(&app, verified_email("subject-1", &app.test_user.email)).await;
+
+    assert_is_redirect_to(&response, "/login/two-factor");
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn the_code_verifier_matches_the_challenge() {
+    let app = spawn_app().await;
+    mount_discovery(&app).await;
+    let request = start_sign_in(&app).await;
+    Mock::given(method("POST"))
+        .and(path("/token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "access_token": "access-token",
+            "token_type": "bearer",
+            "id_token": id_token(&app, &request["nonce"], verified_email("subject-1", &app.test_user.email)),
+        })))
+        .expect(1)
+        .mount(&app.idp_server)
+        .await;
+
+    callback(&app, &request["state"]).await;
+
+    let token_request = app
+        .idp_server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|r| r.url.path() == "/token")
+        .unwrap();
+    let form: HashMap<String, String> = serde_urlencoded::from_bytes(&token_request.body).unwrap();
+    assert_eq!(form["code"], "authorization-code");
+    let challenge = PkceCodeChallenge::from_code_verifier_sha256(&PkceCodeVerifier::new(
+        form["code_verifier"].clone(),
+    ));
+    assert_eq!(challenge.as_str(), request["code_challenge"]);
+}
+
+#[tokio::test]
+async fn unknown_identities_are_refused_without_jit_provisioning() {
+    let app = spawn_app().await;
+    mount_discovery(&app).await;
+
+    let response = sign_in(&app, verified_email("subject-1", "stranger@example.com")).await;
+
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_html("/login").await;
+    assert!(html_page.contains("Your account is not allowed to sign in here."));
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn unverified_emails_are_not_trusted() {
+    let app = spawn_app_linking_emails().await;
+    mount_discovery(&app).await;
+    let claims =
+        claims("subject-1").set_email(Some(EndUserEmail::new(app.test_user.email.clone())));
+
+    let response = sign_in(&app, claims).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn unknown_identities_get_an_editor_with_jit_provisioning() {
+    let app = spawn_app_with(|c| {
+        c.oidc = c.oidc.take().map(|oidc| OidcSettings {
+            allow_jit_provisioning: true,
+            ..oidc
+        })
+    })
+    .await;
+    mount_discovery(&app).await;
+    let claims = verified_email("subject-1", "new.editor@example.com")
+        .set_preferred_username(Some(EndUserUsername::new("new.editor".into())));
+
+    let response = sign_in(&app, claims).await;
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_html("/admin/dashboard").await;
+    assert!(html_page.contains("Welcome new.editor"));
+    assert!(html_page.contains("Your role: viewer"));
+    let editor = sqlx::query!("SELECT email FROM editors WHERE username = 'new.editor'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(editor.email.as_deref(), Some("new.editor@example.com"));
+}
+
+#[tokio::test]
+async fn a_callback_with_the_wrong_state_is_rejected() {
+    let app = spawn_app().await;
+    mount_discovery(&app).await;
+    start_sign_in(&app).await;
+
+    let response = callback(&app, "forged-state").await;
+
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_html("/login").await;
+    assert!(html_page.contains("The sign-in attempt is invalid or has expired"));
+}
+
+#[tokio::test]
+async fn an_id_token_for_another_nonce_is_rejected() {
+    let app = spawn_app().await;
+    mount_discovery(&app).await;
+    let request = start_sign_in(&app).await;
+    Mock::given(method("POST"))
+        .and(path("/token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "access_token": "access-token",
+            "token_type": "bearer",
+            "id_token": id_token(&app, "another-nonce", verified_email("subject-1", &app.test_user.email)),
+        })))
+        .mount(&app.idp_server)
+        .await;
+
+    let response = callback(&app, &request["state"]).await;
+
+    assert_is_redirect_to(&response, "/login");
+    assert_is_redirect_to(&app.get_admin_dashboard().await, "/login");
+}
+
+#[tokio::test]
+async fn disabled_editors_cannot_sign_in_through_the_provider() {
+    let app = spawn_app_linking_emails().await;
+    mount_discovery(&app).await;
+    sqlx::query!(
+        "UPDATE editors SET disabled_at = now() WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = sign_in(&app, verified_email("subject-1", &app.test_user.email)).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
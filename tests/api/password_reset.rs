@@ -0,0 +1,225 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use std::time::Duration;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+/// Reset links are emailed in the background: wait for the email API to be called.
+async fn reset_link(app: &TestApp) -> reqwest::Url {
+    for _ in 0..50 {
+        let requests = app.email_server.received_requests().await.unwrap();
+        if let Some(request) = requests.first() {
+            return app.get_confirmation_links(request).html;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("No password reset email was sent");
+}
+
+fn token(link: &reqwest::Url) -> String {
+    link.query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn forgot_password_emails_a_reset_link_to_the_editor() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_forgot_password(&app.test_user.username).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let link = reset_link(&app).await;
+    assert_eq!(link.path(), "/login/reset-password");
+    let requests = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["To"], app.test_user.email.as_str());
+}
+
+#[tokio::test]
+async fn the_response_is_the_same_for_unknown_usernames() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let known = app.post_forgot_password(&app.test_user.username).await;
+    let known_status = known.status();
+    let known_location = known.headers().get("Location").cloned();
+    let known_page = app.get_html("/login").await;
+    let unknown = app.post_forgot_password("nobody-by-that-name").await;
+    let unknown_page = app.get_html("/login").await;
+
+    assert_eq!(known_status, unknown.status());
+    assert_eq!(known_location, unknown.headers().get("Location").cloned());
+    assert_eq!(known_page, unknown_page);
+    assert!(known_page.contains("a password reset link is on its way"));
+    reset_link(&app).await;
+}
+
+#[tokio::test]
+async fn the_reset_link_sets_a_new_password() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_forgot_password(&app.test_user.username).await;
+    let link = reset_link(&app).await;
+
+    let response = app.api_client.get(link.clone()).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains(&token(&link)));
+
+    let new_password = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "token": token(&link),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_html("/login").await;
+    assert!(html_page.contains("Your password has been reset"));
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &new_password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_reset_link_works_only_once() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_forgot_password(&app.test_user.username).await;
+    let link = reset_link(&app).await;
+    let new_password = uuid::Uuid::new_v4().to_string();
+    let body = serde_json::json!({
+        "token": token(&link),
+        "new_password": &new_password,
+        "new_password_check": &new_password,
+    });
+    let response = app.post_reset_password(&body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.post_reset_password(&body).await;
+    assert_is_redirect_to(&response, "/login/forgot-password");
+    let response = app.api_client.get(link).send().await.unwrap();
+    assert_is_redirect_to(&response, "/login/forgot-password");
+    let html_page = app.get_html("/login/forgot-password").await;
+    assert!(html_page.contains("This password reset link is invalid or has expired"));
+}
+
+#[tokio::test]
+async fn expired_reset_links_are_rejected() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_forgot_password(&app.test_user.username).await;
+    let link = reset_link(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let new_password = uuid::Uuid::new_v4().to_string();
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "token": token(&link),
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login/forgot-password");
+}
+
+#[tokio::test]
+async fn a_rejected_new_password_does_not_use_up_the_link() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_forgot_password(&app.test_user.username).await;
+    let link = reset_link(&app).await;
+
+    let response = app
+        .post_reset_password(&serde_json::json!({
+            "token": token(&link),
+            "new_password": "short",
+            "new_password_check": "short",
+        }))
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/login/reset-password?token={}", token(&link)),
+    );
+
+    let response = app.api_client.get(link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn reset_tokens_are_not_stored_in_clear() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_forgot_password(&app.test_user.username).await;
+    let link = reset_link(&app).await;
+
+    let row = sqlx::query!("SELECT token_hash FROM password_reset_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(row.token_hash, token(&link));
+}
+
+#[tokio::test]
+async fn reset_requests_are_throttled() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..3 {
+        let response = app.post_forgot_password(&app.test_user.username).await;
+        assert_is_redirect_to(&response, "/login");
+    }
+
+    let response = app.post_forgot_password(&app.test_user.username).await;
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+    // Logging in is throttled separately
+    app.test_user.login(&app).await;
+    // Let the emails go out before the mock server checks its expectations
+    tokio::time::sleep(Duration::from_millis(500)).await;
+}
@@ -0,0 +1,130 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestUser};
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "markdown": "Newsletter body",
+        }
+    })
+}
+
+#[tokio::test]
+async fn only_publishers_and_owners_can_publish() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    for (role, expected_status) in [
+        ("owner", 202),
+        ("publisher", 202),
+        ("author", 403),
+        ("viewer", 403),
+    ] {
+        app.test_user.set_role(&app, role).await;
+        let response = app.post_newsletters(newsletter_request_body()).await;
+        assert_eq!(
+            response.status().as_u16(),
+            expected_status,
+            "Unexpected status for the {} role",
+            role
+        );
+    }
+}
+
+#[tokio::test]
+async fn api_tokens_are_limited_by_the_role_of_their_editor() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let token = app
+        .create_api_token(&["newsletter:publish", "subscribers:read"])
+        .await;
+    app.test_user.set_role(&app, "author").await;
+
+    let draft_id = app.create_draft(&newsletter_request_body()).await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletter", &app.addr))
+        .bearer_auth(&token)
+        .json(&serde_json::json!({ "draft_id": draft_id }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 403);
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/subscriptions", &app.addr))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn only_owners_can_manage_editors() {
+    let app = spawn_app().await;
+    app.test_user.set_role(&app, "publisher").await;
+    app.test_user.login(&app).await;
+
+    let html_page = app.get_html("/admin/dashboard").await;
+    assert!(html_page.contains("Your role: publisher"));
+    assert!(!html_page.contains("Manage editors"));
+    let response = app
+        .api_client
+        .get(format!("{}/admin/editors", &app.addr))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn owners_can_change_the_role_of_other_editors() {
+    let app = spawn_app().await;
+    let other_editor = TestUser::generate();
+    other_editor.store(&app.db_pool).await;
+    app.test_user.login(&app).await;
+
+    let html_page = app.get_html("/admin/editors").await;
+    assert!(html_page.contains(&other_editor.username));
+
+    let response = app
+        .api_client
+        .post(format!(
+            "{}/admin/editors/{}/role",
+            &app.addr, other_editor.user_id
+        ))
+        .form(&app.with_csrf_token(&serde_json::json!({ "role": "viewer" })))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_is_redirect_to(&response, "/admin/editors");
+    let role = sqlx::query!(
+        "SELECT role FROM editors WHERE user_id = $1",
+        other_editor.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .role;
+    assert_eq!(role, "viewer");
+
+    // But not their own
+    let response = app
+        .api_client
+        .post(format!(
+            "{}/admin/editors/{}/role",
+            &app.addr, app.test_user.user_id
+        ))
+        .form(&app.with_csrf_token(&serde_json::json!({ "role": "viewer" })))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_is_redirect_to(&response, "/admin/editors");
+    let html_page = app.get_html("/admin/editors").await;
+    assert!(html_page.contains("You can&#x27;t change your own role."));
+}
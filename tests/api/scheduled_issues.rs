@@ -0,0 +1,304 @@
+use crate::helpers::{spawn_app, TestApp};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use kobo::issue_scheduler::{dispatch_due_issues, DISPATCH_LOCK_KEY};
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+fn scheduled_request_body(publish_at: DateTime<Utc>) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "markdown": "Newsletter body",
+        },
+        "publish_at": publish_at.to_rfc3339(),
+    })
+}
+
+/// Schedule an issue, returning its id.
+async fn schedule(app: &TestApp, publish_at: DateTime<Utc>) -> String {
+    let response = app
+        .post_newsletters(scheduled_request_body(publish_at))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+async fn issue_status(app: &TestApp, issue_id: &str) -> String {
+    sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        Uuid::parse_str(issue_id).unwrap()
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+/// Bring the publication time of a scheduled issue forward to now, as if the time had come.
+async fn make_due(app: &TestApp, issue_id: &str) {
+    sqlx::query!(
+        "UPDATE newsletter_issues SET publish_at = now() WHERE newsletter_issue_id = $1",
+        Uuid::parse_str(issue_id).unwrap()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn wait_for_dispatch(app: &TestApp, issue_id: &str) {
+    for _ in 0..200 {
+        if issue_status(app, issue_id).await == "published" {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("The scheduled issue was not dispatched in time");
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_not_delivered_right_away() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    // Editors may use the offset of their audience's timezone.
+    let publish_at = (Utc::now() + Duration::hours(1))
+        .with_timezone(&FixedOffset::east_opt(2 * 3600).unwrap())
+        .to_rfc3339();
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "markdown": "Newsletter body",
+        },
+        "publish_at": publish_at,
+    });
+
+    let response = app.post_newsletters(body).await;
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issue_id = body["newsletter_issue_id"].as_str().unwrap();
+
+    assert_eq!(issue_status(&app, issue_id).await, "scheduled");
+    let response = app.get_scheduled_issues().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let scheduled: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(scheduled[0]["newsletter_issue_id"], issue_id);
+    assert_eq!(
+        scheduled[0]["scheduled_by"],
+        app.test_user.username.as_str()
+    );
+    let listed_at: DateTime<Utc> = scheduled[0]["publish_at"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert_eq!(
+        listed_at.timestamp(),
+        publish_at.parse::<DateTime<Utc>>().unwrap().timestamp()
+    );
+    let queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 0);
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_dispatched_when_due() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = schedule(&app, Utc::now() + Duration::hours(1)).await;
+    make_due(&app, &issue_id).await;
+
+    wait_for_dispatch(&app, &issue_id).await;
+    app.wait_for_pending_deliveries().await;
+    let response = app.get_scheduled_issues().await;
+    let scheduled: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(scheduled, serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn issues_cannot_be_scheduled_in_the_past() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters(scheduled_request_body(Utc::now() - Duration::minutes(1)))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response.text().await.unwrap(),
+        "'publish_at' must be in the future."
+    );
+}
+
+#[tokio::test]
+async fn retrying_a_schedule_request_once_the_issue_is_due_returns_the_saved_response() {
+    let app = spawn_app().await;
+    let body = scheduled_request_body(Utc::now() + Duration::hours(1));
+    let draft_id = app.create_draft(&body).await;
+    let publish_body = serde_json::json!({
+        "draft_id": draft_id,
+        "publish_at": body["publish_at"],
+    });
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    let response = app
+        .post_newsletters_as(&app.test_user, &publish_body, &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let saved_body = response.text().await.unwrap();
+
+    make_due(&app, &draft_id).await;
+    let response = app
+        .post_newsletters_as(&app.test_user, &publish_body, &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    assert_eq!(response.text().await.unwrap(), saved_body);
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_edited() {
+    let app = spawn_app().await;
+    let issue_id = schedule(&app, Utc::now() + Duration::hours(1)).await;
+    let publish_at = Utc::now() + Duration::hours(2);
+
+    let response = app
+        .put_scheduled_issue(
+            &issue_id,
+            &serde_json::json!({
+                "title": "Updated title",
+                "content": {
+                    "markdown": "Updated *body*, with [a link](https://example.com)",
+                },
+                "publish_at": publish_at.to_rfc3339(),
+            }),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 204);
+    let issue = sqlx::query!(
+        r#"
+        SELECT title, markdown_content, text_content, html_content, publish_at AS "publish_at!"
+        FROM newsletter_issues
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.title, "Updated title");
+    assert_eq!(
+        issue.markdown_content.unwrap(),
+        "Updated *body*, with [a link](https://example.com)"
+    );
+    assert_eq!(
+        issue.text_content,
+        "Updated body, with a link [1]\n\n[1] https://example.com\n"
+    );
+    assert!(issue.html_content.contains("<em>body</em>"));
+    assert_eq!(issue.publish_at.timestamp(), publish_at.timestamp());
+}
+
+#[tokio::test]
+async fn scheduled_issues_cannot_be_emptied() {
+    let app = spawn_app().await;
+    let issue_id = schedule(&app, Utc::now() + Duration::hours(1)).await;
+    let mut body = scheduled_request_body(Utc::now() + Duration::hours(1));
+    body["content"]["markdown"] = " ".into();
+
+    let response = app.put_scheduled_issue(&issue_id, &body).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response.text().await.unwrap(),
+        "The content of an issue cannot be empty."
+    );
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_never_delivered() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let issue_id = schedule(&app, Utc::now() + Duration::hours(1)).await;
+
+    let response = app.delete_scheduled_issue(&issue_id).await;
+    assert_eq!(response.status().as_u16(), 204);
+    make_due(&app, &issue_id).await;
+
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    assert_eq!(issue_status(&app, &issue_id).await, "cancelled");
+    let response = app.get_scheduled_issues().await;
+    let scheduled: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(scheduled, serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn dispatched_issues_can_no_longer_be_edited_or_cancelled() {
+    let app = spawn_app().await;
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "markdown": "Newsletter body",
+            }
+        }))
+        .await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issue_id = body["newsletter_issue_id"].as_str().unwrap();
+
+    let response = app
+        .put_scheduled_issue(
+            issue_id,
+            &scheduled_request_body(Utc::now() + Duration::hours(1)),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 409);
+    let response = app.delete_scheduled_issue(issue_id).await;
+    assert_eq!(response.status().as_u16(), 409);
+    let response = app
+        .delete_scheduled_issue(&Uuid::new_v4().to_string())
+        .await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn only_one_instance_dispatches_at_a_time() {
+    let app = spawn_app().await;
+    let issue_id = schedule(&app, Utc::now() + Duration::hours(1)).await;
+    // Play another instance, in the middle of dispatching.
+    let mut other_instance = app.db_pool.acquire().await.unwrap();
+    sqlx::query!("SELECT pg_advisory_lock($1)", DISPATCH_LOCK_KEY)
+        .execute(&mut other_instance)
+        .await
+        .unwrap();
+    make_due(&app, &issue_id).await;
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+    assert_eq!(dispatch_due_issues(&app.db_pool).await.unwrap(), 0);
+    assert_eq!(issue_status(&app, &issue_id).await, "scheduled");
+
+    sqlx::query!("SELECT pg_advisory_unlock($1)", DISPATCH_LOCK_KEY)
+        .fetch_one(&mut other_instance)
+        .await
+        .unwrap();
+    wait_for_dispatch(&app, &issue_id).await;
+}
@@ -0,0 +1,90 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+use actix_session::storage::SessionStore;
+use actix_web::cookie::time::Duration;
+use kobo::session_store::PgSessionStore;
+use std::collections::HashMap;
+
+#[tokio::test]
+async fn logging_in_persists_the_session_in_postgres() {
+    let app = spawn_app().await;
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    });
+
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let saved = sqlx::query!("SELECT user_id FROM sessions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved session");
+    assert_eq!(saved.user_id, Some(app.test_user.user_id));
+}
+
+#[tokio::test]
+async fn saved_sessions_can_be_loaded_back() {
+    let app = spawn_app().await;
+    let store = PgSessionStore::new(app.db_pool.clone());
+    let state = HashMap::from([("key".to_string(), "\"value\"".to_string())]);
+
+    let session_key = store
+        .save(state.clone(), &Duration::minutes(5))
+        .await
+        .unwrap();
+
+    let loaded = store.load(&session_key).await.unwrap();
+    assert_eq!(loaded, Some(state));
+}
+
+#[tokio::test]
+async fn expired_sessions_are_not_loaded() {
+    let app = spawn_app().await;
+    let store = PgSessionStore::new(app.db_pool.clone());
+    let state = HashMap::from([("key".to_string(), "\"value\"".to_string())]);
+
+    let session_key = store.save(state, &Duration::minutes(-1)).await.unwrap();
+
+    assert_eq!(store.load(&session_key).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn expired_sessions_are_cleaned_up_separately_from_logins() {
+    let app = spawn_app().await;
+    let store = PgSessionStore::new(app.db_pool.clone());
+    let state = HashMap::from([("key".to_string(), "\"value\"".to_string())]);
+    store
+        .save(state.clone(), &Duration::minutes(-1))
+        .await
+        .unwrap();
+
+    // Saving a session doesn't sweep the table
+    store.save(state, &Duration::minutes(5)).await.unwrap();
+    let count = || async {
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM sessions"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count
+    };
+    assert_eq!(count().await, 2);
+
+    store.delete_expired_sessions().await.unwrap();
+    assert_eq!(count().await, 1);
+}
+
+#[tokio::test]
+async fn invalidated_sessions_are_not_loaded() {
+    let app = spawn_app().await;
+    let store = PgSessionStore::new(app.db_pool.clone());
+    let user_id = serde_json::to_string(&app.test_user.user_id).unwrap();
+    let state = HashMap::from([("user_id".to_string(), user_id)]);
+    let session_key = store.save(state, &Duration::minutes(5)).await.unwrap();
+
+    store
+        .invalidate_user_sessions(app.test_user.user_id)
+        .await
+        .unwrap();
+
+    assert_eq!(store.load(&session_key).await.unwrap(), None);
+}
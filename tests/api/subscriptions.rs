@@ -1,4 +1,4 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{assert_is_redirect_to, spawn_app};
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
@@ -110,3 +110,35 @@ async fn subscribe_persists_the_new_subscriber() {
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "pending_confirmation");
 }
+
+#[tokio::test]
+async fn browsers_are_redirected_home_with_a_confirmation_message() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions_form(body.to_string()).await;
+    assert_is_redirect_to(&response, "/");
+
+    let html_page = app.get_html("/").await;
+    assert!(html_page.contains("Check your inbox to confirm your subscription."));
+}
+
+#[tokio::test]
+async fn browsers_are_shown_why_a_subscription_was_rejected() {
+    let app = spawn_app().await;
+    let body = "name=Ursula&email=definitely-not-an-email";
+
+    let response = app.post_subscriptions_form(body.to_string()).await;
+    assert_is_redirect_to(&response, "/");
+
+    let html_page = app.get_html("/").await;
+    assert!(html_page.contains(
+        r#"<p class="flash-error"><i>definitely-not-an-email is not a valid subscriber email</i></p>"#
+    ));
+}
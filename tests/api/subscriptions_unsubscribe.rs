@@ -0,0 +1,135 @@
+use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use reqwest::Url;
+use wiremock::matchers::{any, method};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn unsubscribing_without_token_is_rejected_with_400() {
+    let app = spawn_app().await;
+    let response = reqwest::get(format!("{}/subscriptions/unsubscribe", app.addr))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn unsubscribing_with_an_unknown_token_returns_404() {
+    let app = spawn_app().await;
+    let response = reqwest::get(format!(
+        "{}/subscriptions/unsubscribe?token=unknown",
+        app.addr
+    ))
+    .await
+    .unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+
+    let response = app.post_unsubscribe("unknown").await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+/// Subscribe and confirm, then publish an issue to get its unsubscribe link.
+async fn subscribe_and_get_unsubscribe_link(app: &TestApp) -> (ConfirmationLinks, Url) {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let _mock_guard = Mock::given(any())
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.to_string()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "markdown": "[Unsubscribe]({{ unsubscribe_url }})",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.wait_for_pending_deliveries().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let unsubscribe_link = app.get_confirmation_links(email_request).html;
+    (confirmation_links, unsubscribe_link)
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[tokio::test]
+async fn following_the_unsubscribe_link_asks_for_confirmation() {
+    let app = spawn_app().await;
+    let (confirmation_links, unsubscribe_link) = subscribe_and_get_unsubscribe_link(&app).await;
+    let (_, token) = unsubscribe_link.query_pairs().next().unwrap();
+    assert_ne!(
+        confirmation_links.html.query_pairs().next().unwrap().1,
+        token
+    );
+
+    let html_page = app
+        .api_client
+        .get(unsubscribe_link.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains(r#"<form action="/subscriptions/unsubscribe" method="post">"#));
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+
+    let response = app.post_unsubscribe(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+
+    // Both the link and the form keep saying so
+    let response = reqwest::get(unsubscribe_link.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.text().await.unwrap(),
+        "You have been unsubscribed."
+    );
+    let response = app.post_unsubscribe(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_no_longer_get_issues() {
+    let app = spawn_app().await;
+    let (confirmation_links, unsubscribe_link) = subscribe_and_get_unsubscribe_link(&app).await;
+    let (_, token) = unsubscribe_link.query_pairs().next().unwrap();
+    let response = app.post_unsubscribe(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // The confirmation link can't subscribe them again
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 409);
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "markdown": "Newsletter body",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.wait_for_pending_deliveries().await;
+}
@@ -0,0 +1,202 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
+
+/// The code shown by an authenticator app `steps` periods of 30 seconds from now.
+fn totp_code(secret: &str, steps: i64) -> String {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().unwrap();
+    let totp = TOTP::new_unchecked(Algorithm::SHA1, 6, 0, 30, secret, None, String::new());
+    let time = chrono::Utc::now().timestamp() + steps * 30;
+    totp.generate(time as u64)
+}
+
+fn extract_between<'a>(html: &'a str, start: &str, end: &str) -> Vec<&'a str> {
+    html.split(start)
+        .skip(1)
+        .map(|chunk| chunk.split(end).next().unwrap())
+        .collect()
+}
+
+/// Log in and go through enrollment, returning the TOTP secret and the recovery codes.
+pub async fn enable_two_factor(app: &TestApp) -> (String, Vec<String>) {
+    app.test_user.login(app).await;
+    let html_page = app.get_html("/admin/two-factor").await;
+    assert!(html_page.contains("<svg"));
+    let secret = extract_between(&html_page, r#"<code id="totp-secret">"#, "</code>")[0];
+
+    // Reloading the page keeps the same secret
+    let html_page = app.get_html("/admin/two-factor").await;
+    assert!(html_page.contains(secret));
+
+    let response = app
+        .post_enable_two_factor(&serde_json::json!({ "code": totp_code(secret, 0) }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    let recovery_codes: Vec<String> = extract_between(&html_page, "<li><code>", "</code>")
+        .into_iter()
+        .map(String::from)
+        .collect();
+    assert_eq!(recovery_codes.len(), 10);
+    (secret.to_string(), recovery_codes)
+}
+
+#[tokio::test]
+async fn a_wrong_code_does_not_enable_two_factor_authentication() {
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.get_html("/admin/two-factor").await;
+
+    let response = app
+        .post_enable_two_factor(&serde_json::json!({ "code": "000000" }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/two-factor");
+    let html_page = app.get_html("/admin/two-factor").await;
+    assert!(html_page.contains("<p class=\"flash-error\"><i>The code is incorrect"));
+    assert!(html_page.contains("Two-factor authentication is disabled."));
+}
+
+#[tokio::test]
+async fn login_requires_a_second_factor_once_enabled() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_two_factor(&app).await;
+    app.post_logout().await;
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login/two-factor");
+
+    // The password alone doesn't grant access to the admin area
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app
+        .post_login_two_factor(&serde_json::json!({ "code": "123456" }))
+        .await;
+    assert_is_redirect_to(&response, "/login/two-factor");
+
+    // The code used for enrollment can't be replayed, so use the next one
+    let code = totp_code(&secret, 1);
+    let response = app
+        .post_login_two_factor(&serde_json::json!({ "code": &code }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_html("/admin/dashboard").await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+
+    // Nor can the one used to log in
+    app.post_logout().await;
+    app.test_user.login_with_password(&app).await;
+    let response = app
+        .post_login_two_factor(&serde_json::json!({ "code": &code }))
+        .await;
+    assert_is_redirect_to(&response, "/login/two-factor");
+}
+
+#[tokio::test]
+async fn recovery_codes_work_once() {
+    let app = spawn_app().await;
+    let (_, recovery_codes) = enable_two_factor(&app).await;
+    app.post_logout().await;
+
+    app.test_user.login_with_password(&app).await;
+    let response = app
+        .post_login_two_factor(&serde_json::json!({ "code": &recovery_codes[0] }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    app.post_logout().await;
+    app.test_user.login_with_password(&app).await;
+    let response = app
+        .post_login_two_factor(&serde_json::json!({ "code": &recovery_codes[0] }))
+        .await;
+    assert_is_redirect_to(&response, "/login/two-factor");
+}
+
+#[tokio::test]
+async fn the_second_step_requires_a_verified_password() {
+    let app = spawn_app().await;
+    let response = app
+        .post_login_two_factor(&serde_json::json!({ "code": "123456" }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn totp_secrets_are_stored_encrypted() {
+    let app = spawn_app().await;
+    let (secret, _) = enable_two_factor(&app).await;
+
+    let stored = sqlx::query!(
+        "SELECT totp_secret FROM editors WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .totp_secret
+    .unwrap();
+    let plain = Secret::Encoded(secret).to_bytes().unwrap();
+    assert!(!stored.windows(plain.len()).any(|window| window == plain));
+}
+
+#[tokio::test]
+async fn two_factor_authentication_can_be_disabled() {
+    let app = spawn_app().await;
+    let (_, recovery_codes) = enable_two_factor(&app).await;
+
+    let response = app
+        .post_disable_two_factor(&serde_json::json!({ "code": &recovery_codes[0] }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/two-factor");
+    let html_page = app.get_html("/admin/two-factor").await;
+    assert!(html_page.contains("Two-factor authentication has been disabled."));
+
+    app.post_logout().await;
+    app.test_user.login(&app).await;
+}
+
+#[tokio::test]
+async fn basic_auth_publishing_is_rejected_once_two_factor_is_enabled() {
+    let app = spawn_app().await;
+    enable_two_factor(&app).await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "markdown": "Newsletter body",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn api_tokens_still_work_once_two_factor_is_enabled() {
+    let app = spawn_app().await;
+    enable_two_factor(&app).await;
+    let token = app.create_api_token(&["newsletter:publish"]).await;
+    let draft_id = app
+        .create_draft(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "markdown": "Newsletter body",
+            }
+        }))
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletter", &app.addr))
+        .bearer_auth(&token)
+        .header("Idempotency-Key", Uuid::new_v4().to_string())
+        .json(&serde_json::json!({ "draft_id": draft_id }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status().as_u16(), 202);
+}